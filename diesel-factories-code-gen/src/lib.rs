@@ -14,8 +14,8 @@
     unused_qualifications
 )]
 
-use heck::CamelCase;
-use proc_macro2::{Span, TokenStream};
+use heck::{CamelCase, SnakeCase};
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
 use quote::quote;
 use quote::{format_ident, ToTokens};
 use syn::spanned::Spanned;
@@ -23,7 +23,8 @@ use syn::{
     parse::{Parse, ParseStream},
     parse_macro_input,
     punctuated::Punctuated,
-    GenericArgument, Ident, ItemStruct, Lifetime, Path, PathArguments, PathSegment, Token, Type,
+    Attribute, FieldValue, GenericArgument, Generics, Ident, ItemStruct, Lifetime, LitStr, Member,
+    Path, PathArguments, PathSegment, Token, Type,
 };
 
 #[proc_macro_derive(Factory, attributes(factory))]
@@ -34,26 +35,44 @@ pub fn derive_factory(input: proc_macro::TokenStream) -> proc_macro::TokenStream
 }
 
 mod struct_attr {
+    use super::{Backend, Columns};
     use bae::FromAttributes;
-    use syn::{Ident, Path, Type};
+    use syn::{Path, Type};
 
     #[derive(Debug, FromAttributes)]
     pub struct Factory {
         pub model: Type,
         pub table: Path,
         pub connection: Option<Type>,
+        pub async_connection: Option<Type>,
+        pub backend: Option<Backend>,
         pub id: Option<Type>,
-        pub id_name: Option<Ident>,
+        pub id_name: Option<Columns>,
+        pub build_with: Option<Path>,
+        pub before_insert: Option<Path>,
+        pub after_insert: Option<Path>,
     }
 }
 
 mod field_attr {
+    use super::{Columns, PolymorphicTargets};
     use bae::FromAttributes;
-    use syn::Ident;
+    use syn::{Expr, Ident, LitStr, Path};
 
     #[derive(Debug, FromAttributes)]
     pub struct Factory {
-        pub foreign_key_name: Ident,
+        pub computed: Option<Expr>,
+        pub default: Option<Expr>,
+        pub fake: Option<LitStr>,
+        pub foreign_key_name: Option<Columns>,
+        pub inverse_of: Option<Ident>,
+        pub through: Option<Path>,
+        pub owner_key: Option<Ident>,
+        pub references: Option<Columns>,
+        pub target_key: Option<Ident>,
+        pub targets: Option<PolymorphicTargets>,
+        pub transient: Option<()>,
+        pub type_column: Option<Ident>,
     }
 }
 
@@ -61,19 +80,31 @@ mod field_attr {
 struct Input {
     model: Type,
     table: Path,
-    connection: Type,
+    connection: Option<Type>,
+    backend: Backend,
+    async_connection: Option<Type>,
     id_type: Type,
-    id_name: Ident,
+    id_name: Columns,
+    build_with: Option<Path>,
+    before_insert: Option<Path>,
+    after_insert: Option<Path>,
     factory_name: Ident,
     fields: Vec<(Ident, Type)>,
-    associations: Vec<(Ident, AssociationType, Ident)>,
-    lifetime: Option<Lifetime>,
+    transient_fields: Vec<(Ident, Type)>,
+    computed_fields: Vec<ComputedField>,
+    associations: Vec<(Ident, AssociationType, Columns)>,
+    polymorphic: Vec<PolymorphicField>,
+    has_many: Vec<HasManyField>,
+    many_to_many: Vec<ManyToManyField>,
+    variants: Vec<Variant>,
+    defaults: Vec<(Ident, Option<TokenStream>)>,
+    generics: Generics,
 }
 
 impl Parse for Input {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let ItemStruct {
-            attrs,
+            mut attrs,
             ident: factory_name,
             generics,
             fields: item_strut_fields,
@@ -83,22 +114,75 @@ impl Parse for Input {
             vis: _,
         } = input.parse::<ItemStruct>()?;
 
+        let variants = take_variants(&mut attrs)?;
         let struct_attr::Factory {
             model,
             table,
             connection,
+            async_connection,
+            backend: explicit_backend,
             id,
             id_name,
+            build_with,
+            before_insert,
+            after_insert,
         } = struct_attr::Factory::from_attributes(&attrs)?;
 
-        let connection =
-            connection.unwrap_or_else(|| syn::parse2(quote! { diesel::pg::PgConnection }).unwrap());
+        // Callbacks are plain functions taking a blocking connection
+        if async_connection.is_some() {
+            if let Some(callback) = before_insert.as_ref().or(after_insert.as_ref()) {
+                return Err(syn::Error::new(
+                    callback.span(),
+                    "Insert callbacks aren't supported on factories with an `async_connection`",
+                ));
+            }
+        }
+
+        // Factories with only an async connection don't get a `Factory` implementation
+        let connection = if connection.is_none() && async_connection.is_none() {
+            Some(syn::parse2(quote! { diesel::pg::PgConnection }).unwrap())
+        } else {
+            connection
+        };
+        // `BuildFactory` extends `Factory`, which isn't implemented without a blocking connection
+        if connection.is_none() {
+            if let Some(build_with) = &build_with {
+                return Err(syn::Error::new(
+                    build_with.span(),
+                    "`build_with` isn't supported on factories with only an `async_connection`, also set `connection`",
+                ));
+            }
+        }
+        let backend = explicit_backend.unwrap_or_else(|| {
+            connection
+                .as_ref()
+                .map(Backend::from_connection)
+                .unwrap_or(Backend::Pg)
+        });
+
+        // Async inserts load the inserted row with `RETURNING`
+        if let Some(async_connection) = &async_connection {
+            let async_backend =
+                explicit_backend.unwrap_or_else(|| Backend::from_connection(async_connection));
+            if async_backend != Backend::Pg {
+                return Err(syn::Error::new(
+                    async_connection.span(),
+                    "Async factories only support Postgres since inserted rows are loaded with `RETURNING`",
+                ));
+            }
+        }
         let id_type = id.unwrap_or_else(|| syn::parse2(quote! { i32 }).unwrap());
         let id_name = id_name.unwrap_or_else(|| syn::parse2(quote! { id }).unwrap());
 
         // parse fields and associations
         let mut fields = Vec::new();
+        let mut transient_fields = Vec::new();
+        let mut computed_fields = Vec::new();
         let mut associations = Vec::new();
+        let mut polymorphic = Vec::new();
+        let mut has_many = Vec::new();
+        let mut many_to_many = Vec::new();
+        let mut defaults = Vec::new();
         for field in item_strut_fields {
             let field_span = field.span();
 
@@ -106,232 +190,1793 @@ impl Parse for Input {
                 .ident
                 .ok_or_else(|| syn::Error::new(field_span, "Unnamed fields are not supported"))?;
 
-            let field_ty = field.ty.clone();
+            let field_ty = field.ty.clone();
+
+            let attr = field_attr::Factory::try_from_attributes(&field.attrs)?;
+            let field_attr::Factory {
+                computed,
+                default,
+                fake,
+                foreign_key_name,
+                inverse_of,
+                through,
+                owner_key,
+                references,
+                target_key,
+                targets,
+                transient,
+                type_column,
+            } = attr.unwrap_or(field_attr::Factory {
+                computed: None,
+                default: None,
+                fake: None,
+                foreign_key_name: None,
+                inverse_of: None,
+                through: None,
+                owner_key: None,
+                references: None,
+                target_key: None,
+                targets: None,
+                transient: None,
+                type_column: None,
+            });
+            let default = match (default, fake) {
+                (Some(_), Some(_)) => {
+                    return Err(syn::Error::new(
+                        field_span,
+                        "`default` and `fake` can't both be set",
+                    ))
+                }
+                (Some(default), None) => Some(quote! { #default }),
+                (None, Some(fake)) => {
+                    let generator = Ident::new(&fake.value(), fake.span());
+                    Some(quote! {
+                        std::convert::Into::into(diesel_factories::fake::#generator())
+                    })
+                }
+                (None, None) => None,
+            };
+            defaults.push((name.clone(), default));
+
+            let has_association_attr = foreign_key_name.is_some()
+                || inverse_of.is_some()
+                || through.is_some()
+                || owner_key.is_some()
+                || references.is_some()
+                || target_key.is_some()
+                || targets.is_some()
+                || type_column.is_some();
+
+            let only_allowed_on = |is_set: bool, attribute: &str, kind: &str| {
+                if is_set {
+                    Err(syn::Error::new(
+                        field_span,
+                        format!("`{}` is only allowed on {} fields", attribute, kind),
+                    ))
+                } else {
+                    Ok(())
+                }
+            };
+            let only_allowed_on_many_to_many = || {
+                only_allowed_on(through.is_some(), "through", "`ManyToMany`")?;
+                only_allowed_on(owner_key.is_some(), "owner_key", "`ManyToMany`")?;
+                only_allowed_on(target_key.is_some(), "target_key", "`ManyToMany`")
+            };
+            let only_allowed_on_polymorphic = || {
+                only_allowed_on(targets.is_some(), "targets", "`Polymorphic`")?;
+                only_allowed_on(type_column.is_some(), "type_column", "`Polymorphic`")
+            };
+
+            if let Some(computed) = computed {
+                if transient.is_some() || has_association_attr {
+                    return Err(syn::Error::new(
+                        field_span,
+                        "Computed fields can't be transient or have association `#[factory]` attributes",
+                    ));
+                }
+
+                // The field is `None` until set with the builder method
+                let ty = generic_argument_of(&field_ty, "Option").ok_or_else(|| {
+                    syn::Error::new(
+                        field_ty.span(),
+                        "Computed fields must be `Option`s, which are `None` unless overridden",
+                    )
+                })?;
+
+                computed_fields.push(ComputedField { name, ty, computed });
+            } else if transient.is_some() {
+                // Transient fields only get a builder method, whatever their type
+                if has_association_attr {
+                    return Err(syn::Error::new(
+                        field_span,
+                        "Transient fields can't have association `#[factory]` attributes",
+                    ));
+                }
+
+                transient_fields.push((name, field.ty));
+            } else if let Ok(mut association_type) = AssociationType::new(field_ty.clone()) {
+                only_allowed_on(inverse_of.is_some(), "inverse_of", "`HasMany`")?;
+                only_allowed_on_many_to_many()?;
+                only_allowed_on_polymorphic()?;
+
+                let foreign_key_name = foreign_key_name.unwrap_or_else(|| {
+                    let id_suffix = match references.as_ref().unwrap_or(&id_name).single() {
+                        Some(id_name) => id_name.clone(),
+                        None => format_ident!("id"),
+                    };
+                    Columns::from(format_ident!("{}_{}", name, id_suffix))
+                });
+                association_type.references = references;
+
+                associations.push((name, association_type, foreign_key_name));
+            } else if let Some((ty, is_optional)) = polymorphic_type(&field_ty) {
+                only_allowed_on(inverse_of.is_some(), "inverse_of", "`HasMany`")?;
+                only_allowed_on(references.is_some(), "references", "`Association`")?;
+                only_allowed_on_many_to_many()?;
+
+                let lifetime = match &ty {
+                    Type::Path(type_path) => type_path.path.segments.last().and_then(|segment| {
+                        match &segment.arguments {
+                            PathArguments::AngleBracketed(args) => match args.args.first()? {
+                                GenericArgument::Lifetime(lifetime) => Some(lifetime.clone()),
+                                _ => None,
+                            },
+                            _ => None,
+                        }
+                    }),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    syn::Error::new(ty.span(), "Expected a lifetime like `Polymorphic<'a, ...>`")
+                })?;
+
+                let targets = targets.ok_or_else(|| {
+                    syn::Error::new(
+                        field_span,
+                        "`Polymorphic` fields need the models they point at set with `#[factory(targets = (...))]`",
+                    )
+                })?;
+
+                let id_column = match foreign_key_name {
+                    Some(columns) => columns.single().cloned().ok_or_else(|| {
+                        syn::Error::new(
+                            field_span,
+                            "`Polymorphic` associations have a single id column",
+                        )
+                    })?,
+                    None => format_ident!("{}_id", name),
+                };
+                let type_column = type_column.unwrap_or_else(|| format_ident!("{}_type", name));
+
+                polymorphic.push(PolymorphicField {
+                    name,
+                    ty,
+                    lifetime,
+                    is_optional,
+                    type_column,
+                    id_column,
+                    targets: targets.0,
+                });
+            } else if let Ok(association_type) = AssociationType::with_name(field_ty, "ManyToMany")
+            {
+                only_allowed_on(
+                    foreign_key_name.is_some(),
+                    "foreign_key_name",
+                    "association",
+                )?;
+                only_allowed_on(inverse_of.is_some(), "inverse_of", "`HasMany`")?;
+                only_allowed_on(references.is_some(), "references", "`Association`")?;
+                only_allowed_on_polymorphic()?;
+                if association_type.is_optional {
+                    return Err(syn::Error::new(
+                        field_span,
+                        "`ManyToMany` fields can't be optional, use an empty `ManyToMany` instead",
+                    ));
+                }
+
+                let through = through.ok_or_else(|| {
+                    syn::Error::new(
+                        field_span,
+                        "`ManyToMany` fields need the join table set with `#[factory(through = ...)]`",
+                    )
+                })?;
+
+                // The join table columns are named after the models by default
+                let owner_key = match owner_key {
+                    Some(owner_key) => owner_key,
+                    None => {
+                        let model_name = snake_case_name(&model).ok_or_else(|| {
+                            syn::Error::new(field_span, "Can't guess the column, set `owner_key`")
+                        })?;
+                        format_ident!("{}_id", model_name)
+                    }
+                };
+                let target_key = match target_key {
+                    Some(target_key) => target_key,
+                    None => {
+                        let model_name =
+                            snake_case_name(&association_type.model_type).ok_or_else(|| {
+                                syn::Error::new(
+                                    field_span,
+                                    "Can't guess the column, set `target_key`",
+                                )
+                            })?;
+                        format_ident!("{}_id", model_name)
+                    }
+                };
+
+                many_to_many.push(ManyToManyField {
+                    name,
+                    association_type,
+                    through,
+                    owner_key,
+                    target_key,
+                });
+            } else if let Some(factory_type) = has_many_factory_type(&field.ty) {
+                only_allowed_on(
+                    foreign_key_name.is_some(),
+                    "foreign_key_name",
+                    "association",
+                )?;
+                only_allowed_on(references.is_some(), "references", "`Association`")?;
+                only_allowed_on_many_to_many()?;
+                only_allowed_on_polymorphic()?;
+
+                // Children point back to us through an association named after our model by default
+                let inverse_of = match inverse_of {
+                    Some(inverse_of) => inverse_of,
+                    None => {
+                        let model_name = snake_case_name(&model).ok_or_else(|| {
+                            syn::Error::new(
+                                field_span,
+                                "Can't guess the association on the children, set `inverse_of`",
+                            )
+                        })?;
+                        format_ident!("{}", model_name)
+                    }
+                };
+
+                // The builder method traits are generated next to the child factory
+                let mut inverse_setter = match &factory_type {
+                    Type::Path(type_path) => type_path.path.clone(),
+                    _ => {
+                        return Err(syn::Error::new(
+                            factory_type.span(),
+                            "Expected the type of a factory",
+                        ))
+                    }
+                };
+                if let Some(segment) = inverse_setter.segments.last_mut() {
+                    segment.ident = format_ident!(
+                        "Set{}On{}",
+                        inverse_of.to_string().to_camel_case(),
+                        segment.ident
+                    );
+                    segment.arguments = PathArguments::None;
+                }
+
+                has_many.push(HasManyField {
+                    name,
+                    ty: field.ty.clone(),
+                    factory_type,
+                    inverse_of,
+                    inverse_setter,
+                });
+            } else {
+                if has_association_attr {
+                    return Err(syn::Error::new(
+                        field_span,
+                        "`#[factory]` attributes are only allowed on association fields",
+                    ));
+                }
+
+                fields.push((name, field.ty));
+            }
+        }
+
+        // Primary keys without `RETURNING` are looked up with the values we inserted, which we
+        // only know for fields
+        if id_name.is_composite() && !backend.supports_returning() {
+            if let Some(column) = id_name
+                .iter()
+                .find(|column| !fields.iter().any(|(name, _)| name == *column))
+            {
+                return Err(syn::Error::new(
+                    column.span(),
+                    "Columns of composite primary keys must be fields on the factory for databases without `RETURNING`",
+                ));
+            }
+        }
+
+        // Polymorphic associations are inserted through a blocking connection and have no id
+        // to build models with
+        if let Some(field) = polymorphic.first() {
+            if async_connection.is_some() || build_with.is_some() {
+                return Err(syn::Error::new(
+                    field.name.span(),
+                    "`Polymorphic` associations aren't supported on factories with an `async_connection` or `build_with`",
+                ));
+            }
+        }
+
+        // Variants are built from the builder methods, so every field they set must have one
+        for variant in &variants {
+            let is_field = |name: &Ident| {
+                fields.iter().any(|(field, _)| field == name)
+                    || transient_fields.iter().any(|(field, _)| field == name)
+                    || computed_fields.iter().any(|field| &field.name == name)
+                    || associations.iter().any(|(field, _, _)| field == name)
+                    || polymorphic.iter().any(|field| &field.name == name)
+                    || has_many.iter().any(|field| &field.name == name)
+                    || many_to_many.iter().any(|field| &field.name == name)
+            };
+
+            if is_field(&variant.name) {
+                return Err(syn::Error::new(
+                    variant.name.span(),
+                    format!("Variant `{}` has the same name as a field", variant.name),
+                ));
+            }
+
+            for field_value in &variant.fields {
+                match &field_value.member {
+                    Member::Named(name) if is_field(name) => {}
+                    member => {
+                        return Err(syn::Error::new(
+                            member.span(),
+                            format!(
+                                "Unknown field `{}` in variant `{}`",
+                                member.to_token_stream(),
+                                variant.name
+                            ),
+                        ))
+                    }
+                }
+
+                if field_value.colon_token.is_none() {
+                    return Err(syn::Error::new(
+                        field_value.span(),
+                        "Expected a value, such as `field: value`",
+                    ));
+                }
+            }
+        }
+
+        Ok(Input {
+            model,
+            table,
+            connection,
+            backend,
+            async_connection,
+            id_type,
+            id_name,
+            build_with,
+            before_insert,
+            after_insert,
+            factory_name,
+            fields,
+            transient_fields,
+            computed_fields,
+            associations,
+            polymorphic,
+            has_many,
+            many_to_many,
+            variants,
+            defaults,
+            generics,
+        })
+    }
+}
+
+impl ToTokens for Input {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        tokens.extend(self.factory_trait_impl());
+        tokens.extend(self.async_factory_trait_impl());
+        tokens.extend(self.build_factory_trait_impl());
+        tokens.extend(self.insert_with_has_many_methods());
+        tokens.extend(self.field_builder_methods());
+        tokens.extend(self.association_builder_methods());
+        tokens.extend(self.variant_methods());
+        tokens.extend(self.default_impl());
+    }
+}
+
+impl Input {
+    fn factory_trait_impl(&self) -> TokenStream {
+        let connection_type = match &self.connection {
+            Some(connection_type) => connection_type,
+            None => return quote! {},
+        };
+
+        let factory = &self.factory_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let model_type = &self.model;
+        let id_type = &self.id_type;
+        let table_path = &self.table;
+        let map_insert_err = self.map_insert_err();
+        let id_for_model = self.id_for_model();
+
+        let (insert_code, insert_each_method) = if self.no_fields() {
+            let insert_code = self.insert_returning_model(quote! {
+                diesel_factories::diesel::insert_into(#table_path::table).default_values()
+            });
+
+            // Multiple rows can't be inserted with `DEFAULT VALUES` so we rely on the default
+            // implementation that inserts one row at a time
+            (insert_code, quote! {})
+        } else {
+            let values = self.insert_values(&quote! { self }, AssociationKeys::Insert);
+            let insert_code = self.insert_returning_model(quote! {
+                diesel_factories::diesel::insert_into(#table_path::table).values(#values)
+            });
+
+            // Without `RETURNING` we can't get all the inserted rows back from a single `INSERT`
+            // so we rely on the default implementation here as well. The same goes for factories
+            // with children or join table rows since they're inserted one parent at a time, and
+            // for factories with callbacks which run for each model.
+            let insert_each_method = if self.backend.supports_returning()
+                && self.has_many.is_empty()
+                && self.many_to_many.is_empty()
+                && !self.has_callbacks()
+            {
+                let association_keys = self.insert_each_association_keys();
+                let values = self.insert_values(&quote! { factory }, AssociationKeys::Inserted);
+                quote! {
+                    fn try_insert_each<I>(
+                        factories: I,
+                        con: diesel_factories::ConnectionRef<'_, Self::Connection>,
+                    ) -> std::result::Result<std::vec::Vec<Self::Model>, diesel_factories::FactoryError>
+                    where
+                        I: std::iter::IntoIterator<Item = Self>,
+                    {
+                        use diesel_factories::diesel::ExpressionMethods;
+                        let factories = factories.into_iter().collect::<std::vec::Vec<_>>();
+                        if factories.is_empty() {
+                            return Ok(std::vec::Vec::new());
+                        }
+
+                        #(#association_keys)*
+                        let values = factories
+                            .iter()
+                            .map(|factory| -> std::result::Result<_, diesel_factories::FactoryError> {
+                                Ok(#values)
+                            })
+                            .collect::<std::result::Result<std::vec::Vec<_>, _>>()?;
+                        let query = diesel_factories::diesel::insert_into(#table_path::table)
+                            .values(values);
+                        diesel_factories::diesel::RunQueryDsl::get_results::<Self::Model>(query, con)
+                            #map_insert_err
+                    }
+                }
+            } else {
+                quote! {}
+            };
+
+            (insert_code, insert_each_method)
+        };
+        let insert_code = self.insert_related_after(insert_code, false);
+        let insert_code = self.with_callbacks(insert_code);
+        let mut_self = if self.has_callbacks() {
+            quote! { mut self }
+        } else {
+            quote! { self }
+        };
+
+        quote! {
+            impl #impl_generics diesel_factories::Factory for #factory #ty_generics #where_clause {
+                type Model = #model_type;
+                type Id = #id_type;
+                type Connection = #connection_type;
+
+                fn try_insert(
+                    #mut_self,
+                    con: diesel_factories::ConnectionRef<'_, Self::Connection>,
+                ) -> std::result::Result<Self::Model, diesel_factories::FactoryError> {
+                    use diesel_factories::diesel::ExpressionMethods;
+                    #insert_code
+                }
+
+                #insert_each_method
+
+                fn id_for_model(model: &Self::Model) -> Self::Id {
+                    #id_for_model
+                }
+            }
+        }
+    }
+
+    /// Run the insert statement `query` and get the inserted row back as the model.
+    fn insert_returning_model(&self, query: TokenStream) -> TokenStream {
+        let table_path = &self.table;
+        let id_name = &self.id_name;
+        let map_insert_err = self.map_insert_err();
+
+        let last_insert_id_sql = match self.backend.last_insert_id_sql() {
+            Some(sql) => sql,
+            None => {
+                return quote! {
+                    let query = #query;
+                    diesel_factories::diesel::RunQueryDsl::get_result::<Self::Model>(query, con)
+                        #map_insert_err
+                };
+            }
+        };
+
+        // Primary keys supplied by the factory itself aren't generated by the database so we look
+        // up the row using the values we inserted
+        let inserted_row = if self.has_id_fields() {
+            let mut columns = id_name.iter();
+            let first = columns.next().unwrap();
+            columns.fold(
+                quote! { #table_path::#first.eq(&self.#first) },
+                |condition, column| {
+                    quote! {
+                        diesel_factories::diesel::BoolExpressionMethods::and(
+                            #condition,
+                            #table_path::#column.eq(&self.#column),
+                        )
+                    }
+                },
+            )
+        } else {
+            let id_name = id_name.single().unwrap();
+            quote! {
+                #table_path::#id_name.eq(
+                    diesel_factories::diesel::dsl::sql::<
+                        <#table_path::#id_name as diesel_factories::diesel::Expression>::SqlType,
+                    >(#last_insert_id_sql)
+                )
+            }
+        };
+
+        quote! {
+            let query = #query;
+            diesel_factories::diesel::RunQueryDsl::execute(query, con)#map_insert_err?;
+
+            let query = diesel_factories::diesel::QueryDsl::filter(#table_path::table, #inserted_row);
+            diesel_factories::diesel::RunQueryDsl::get_result::<Self::Model>(query, con)
+                #map_insert_err
+        }
+    }
+
+    /// Insert the factories of each association of `factories` with one `try_insert_each` per
+    /// association, into an iterator of the keys named `{association}_keys`.
+    ///
+    /// Must be used in a function returning `Result<_, FactoryError>` with `con` in scope.
+    fn insert_each_association_keys(&self) -> Vec<TokenStream> {
+        self.associations
+            .iter()
+            .map(|(name, association_type, _)| {
+                let keys = format_ident!("{}_keys", name);
+                let association_name = name.to_string();
+                // Unset optional associations don't get a key
+                let associations = if association_type.is_optional {
+                    quote! { factories.iter().filter_map(|factory| factory.#name.as_ref()) }
+                } else {
+                    quote! { factories.iter().map(|factory| &factory.#name) }
+                };
+                let insert = association_type.returning_key(
+                    "try_insert_each",
+                    "",
+                    vec![associations, quote! { con }],
+                );
+
+                quote! {
+                    let mut #keys = diesel_factories::Association::#insert
+                        .map_err(|err| err.within_association(#association_name))?
+                        .into_iter();
+                }
+            })
+            .collect()
+    }
+
+    /// The tuple of column assignments for inserting `factory`, with the foreign keys of
+    /// associations from `association_keys`.
+    ///
+    /// Must be used in a function returning `Result<_, FactoryError>` with `con` in scope, or an
+    /// async block returning one for async factories.
+    fn insert_values(
+        &self,
+        factory: &TokenStream,
+        association_keys: AssociationKeys,
+    ) -> TokenStream {
+        let table_path = &self.table;
+
+        let values = self.fields.iter().map(|(name, _)| {
+            quote! { #table_path::#name.eq(&#factory.#name) }
+        });
+        let values = values.chain(self.computed_fields.iter().map(|field| {
+            let name = &field.name;
+            let value = field.value(factory);
+            quote! { #table_path::#name.eq(#value) }
+        }));
+        let values = values.chain(self.associations.iter().map(
+            |(name, association_type, foreign_key)| {
+                let association_name = name.to_string();
+                let map_association_err = quote! {
+                    .map_err(|err| err.within_association(#association_name))?
+                };
+
+                let insert = match association_keys {
+                    AssociationKeys::Insert => {
+                        association_type.returning_key("try_insert", "", vec![quote! { con }])
+                    }
+                    AssociationKeys::InsertAsync => {
+                        let insert = association_type.returning_key(
+                            "try_insert",
+                            "_async",
+                            vec![quote! { &mut *con }],
+                        );
+                        quote! { #insert.await }
+                    }
+                    AssociationKeys::Inserted => {
+                        // There's a key for each factory, or each one with the association set
+                        let keys = format_ident!("{}_keys", name);
+                        let id = if association_type.is_optional {
+                            quote! { #factory.#name.as_ref().map(|_| #keys.next().unwrap()) }
+                        } else {
+                            quote! { #keys.next().unwrap() }
+                        };
+                        return self.foreign_key_values(foreign_key, association_type, id);
+                    }
+                };
+                let id = if association_type.is_optional {
+                    quote! {
+                        match &#factory.#name {
+                            std::option::Option::Some(inner) => {
+                                std::option::Option::Some(inner.#insert#map_association_err)
+                            }
+                            std::option::Option::None => std::option::Option::None,
+                        }
+                    }
+                } else {
+                    quote! {
+                        #factory.#name.#insert#map_association_err
+                    }
+                };
+
+                self.foreign_key_values(foreign_key, association_type, id)
+            },
+        ));
+        // Factories with polymorphic associations don't have an async connection
+        let values = values.chain(self.polymorphic.iter().map(|field| {
+            let PolymorphicField {
+                name,
+                type_column,
+                id_column,
+                ..
+            } = field;
+            let association_name = name.to_string();
+            let map_association_err = quote! {
+                .map_err(|err| err.within_association(#association_name))?
+            };
+
+            let (type_name, id) = if field.is_optional {
+                (
+                    quote! { #factory.#name.as_ref().map(|inner| inner.type_name()) },
+                    quote! {
+                        #factory.#name.as_ref().map(|inner| {
+                            inner.try_insert_returning_id(con)
+                        }).transpose()#map_association_err
+                    },
+                )
+            } else {
+                (
+                    quote! { #factory.#name.type_name() },
+                    quote! { #factory.#name.try_insert_returning_id(con)#map_association_err },
+                )
+            };
+
+            quote! {
+                #table_path::#type_column.eq(#type_name),
+                #table_path::#id_column.eq(#id)
+            }
+        }));
+
+        quote! { ( #(#values),* ) }
+    }
+
+    fn async_factory_trait_impl(&self) -> TokenStream {
+        let connection_type = match &self.async_connection {
+            Some(connection_type) => connection_type,
+            None => return quote! {},
+        };
+
+        let factory = &self.factory_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let model_type = &self.model;
+        let id_type = &self.id_type;
+        let table_path = &self.table;
+        let map_insert_err = self.map_insert_err();
+        let id_for_model = self.id_for_model();
+
+        let insert_code = if self.no_fields() {
+            quote! {
+                let query = diesel_factories::diesel::insert_into(#table_path::table)
+                    .default_values();
+                diesel_factories::diesel_async::RunQueryDsl::get_result::<Self::Model>(query, con)
+                    .await
+                    #map_insert_err
+            }
+        } else {
+            let values = self.insert_values(&quote! { self }, AssociationKeys::InsertAsync);
+            quote! {
+                let values = #values;
+                let query = diesel_factories::diesel::insert_into(#table_path::table)
+                    .values(values);
+                diesel_factories::diesel_async::RunQueryDsl::get_result::<Self::Model>(query, con)
+                    .await
+                    #map_insert_err
+            }
+        };
+
+        let insert_code = self.insert_related_after(insert_code, true);
+
+        quote! {
+            impl #impl_generics diesel_factories::AsyncFactory for #factory #ty_generics #where_clause {
+                type Model = #model_type;
+                type Id = #id_type;
+                type Connection = #connection_type;
+
+                fn try_insert<'__con>(
+                    self,
+                    con: &'__con mut Self::Connection,
+                ) -> diesel_factories::BoxFuture<
+                    '__con,
+                    std::result::Result<Self::Model, diesel_factories::FactoryError>,
+                >
+                where
+                    Self: '__con,
+                {
+                    std::boxed::Box::pin(async move {
+                        use diesel_factories::diesel::ExpressionMethods;
+                        #insert_code
+                    })
+                }
+
+                fn id_for_model(model: &Self::Model) -> Self::Id {
+                    #id_for_model
+                }
+            }
+        }
+    }
+
+    /// Wrap `insert_code` so the join table rows of `ManyToMany` fields and the children of
+    /// `HasMany` fields are inserted after the model.
+    fn insert_related_after(&self, insert_code: TokenStream, is_async: bool) -> TokenStream {
+        if self.has_many.is_empty() && self.many_to_many.is_empty() {
+            return insert_code;
+        }
+
+        let join_rows = self
+            .many_to_many
+            .iter()
+            .map(|field| self.insert_join_rows(field, is_async));
+        let children = self.has_many.iter().map(|field| {
+            let name = &field.name;
+            // The factory is still passed to `after_insert` once the children are inserted
+            let children = if self.after_insert.is_some() && !is_async {
+                quote! { std::mem::take(&mut self.#name) }
+            } else {
+                quote! { self.#name }
+            };
+            self.insert_children(field, children, is_async)
+        });
+
+        quote! {
+            let model = { #insert_code }?;
+            #(#join_rows)*
+            #(#children;)*
+            Ok(model)
+        }
+    }
+
+    /// Run the `before_insert` callback before `insert_code` and the `after_insert` callback with
+    /// the model it returns.
+    fn with_callbacks(&self, insert_code: TokenStream) -> TokenStream {
+        if !self.has_callbacks() {
+            return insert_code;
+        }
+
+        let map_insert_err = self.map_insert_err();
+        let before_insert = self.before_insert.iter().map(|before_insert| {
+            quote! { #before_insert(&mut self, con)#map_insert_err?; }
+        });
+        let after_insert = self.after_insert.iter().map(|after_insert| {
+            quote! { #after_insert(&model, &self, con)#map_insert_err?; }
+        });
+
+        quote! {
+            #(#before_insert)*
+            let model = { #insert_code }?;
+            #(#after_insert)*
+            Ok(model)
+        }
+    }
+
+    /// Insert the associations of a `ManyToMany` field and link each of them to `model` with a
+    /// row in the join table.
+    ///
+    /// Must be used in a function returning `Result<_, FactoryError>` with `con` in scope.
+    fn insert_join_rows(&self, field: &ManyToManyField, is_async: bool) -> TokenStream {
+        let ManyToManyField {
+            name,
+            through,
+            owner_key,
+            target_key,
+            ..
+        } = field;
+        let association_name = name.to_string();
+        let factory_name = self.factory_name.to_string();
+        let join_table_name = through
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+            .unwrap_or_default();
+
+        let (owner_id, insert_association, execute) = if is_async {
+            (
+                quote! { <Self as diesel_factories::AsyncFactory>::id_for_model(&model) },
+                quote! { association.try_insert_returning_id_async(&mut *con).await },
+                quote! { diesel_factories::diesel_async::RunQueryDsl::execute(query, &mut *con).await },
+            )
+        } else {
+            (
+                quote! { <Self as diesel_factories::Factory>::id_for_model(&model) },
+                quote! { association.try_insert_returning_id(con) },
+                quote! { diesel_factories::diesel::RunQueryDsl::execute(query, con) },
+            )
+        };
+
+        quote! {
+            {
+                let owner_id = #owner_id;
+                let mut rows = std::vec::Vec::new();
+                for association in self.#name.associations() {
+                    let target_id = #insert_association
+                        .map_err(|err| err.within_association(#association_name))?;
+                    rows.push((
+                        #through::#owner_key.eq(owner_id.clone()),
+                        #through::#target_key.eq(target_id),
+                    ));
+                }
+                if !rows.is_empty() {
+                    let query = diesel_factories::diesel::insert_into(#through::table).values(rows);
+                    #execute.map_err(|err| {
+                        diesel_factories::FactoryError::new(#factory_name, #join_table_name, err)
+                    })?;
+                }
+            }
+        }
+    }
+
+    /// Insert each factory in `children` with its association set to `model`, returning the
+    /// inserted children.
+    ///
+    /// Must be used in a function returning `Result<_, FactoryError>` with `con` in scope.
+    fn insert_children(
+        &self,
+        field: &HasManyField,
+        children: TokenStream,
+        is_async: bool,
+    ) -> TokenStream {
+        let HasManyField {
+            name,
+            inverse_of,
+            inverse_setter,
+            ..
+        } = field;
+        let association_name = name.to_string();
+
+        let insert_child = if is_async {
+            quote! { diesel_factories::AsyncFactory::try_insert(child, &mut *con).await }
+        } else {
+            quote! { diesel_factories::Factory::try_insert(child, con) }
+        };
+
+        quote! {
+            {
+                let mut inserted = std::vec::Vec::new();
+                for child in #children {
+                    let child = #inverse_setter::#inverse_of(child, &model);
+                    inserted.push(
+                        #insert_child.map_err(|err| err.within_association(#association_name))?
+                    );
+                }
+                inserted
+            }
+        }
+    }
+
+    fn insert_with_has_many_methods(&self) -> TokenStream {
+        if self.connection.is_none() || self.has_many.is_empty() {
+            return quote! {};
+        }
+
+        let factory = &self.factory_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        let methods = self.has_many.iter().map(|field| {
+            let name = &field.name;
+            let child_factory = &field.factory_type;
+            let insert_with = format_ident!("insert_with_{}", name);
+            let try_insert_with = format_ident!("try_insert_with_{}", name);
+            let insert_children = self.insert_children(field, quote! { children }, false);
+
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub fn #insert_with(
+                    self,
+                    con: diesel_factories::ConnectionRef<
+                        '_,
+                        <Self as diesel_factories::Factory>::Connection,
+                    >,
+                ) -> (
+                    <Self as diesel_factories::Factory>::Model,
+                    std::vec::Vec<<#child_factory as diesel_factories::Factory>::Model>,
+                ) {
+                    match self.#try_insert_with(con) {
+                        Ok(models) => models,
+                        Err(err) => panic!("Insert of factory failed: {}", err),
+                    }
+                }
+
+                #[allow(missing_docs, dead_code)]
+                pub fn #try_insert_with(
+                    mut self,
+                    con: diesel_factories::ConnectionRef<
+                        '_,
+                        <Self as diesel_factories::Factory>::Connection,
+                    >,
+                ) -> std::result::Result<
+                    (
+                        <Self as diesel_factories::Factory>::Model,
+                        std::vec::Vec<<#child_factory as diesel_factories::Factory>::Model>,
+                    ),
+                    diesel_factories::FactoryError,
+                > {
+                    let children = std::mem::take(&mut self.#name);
+                    let model = diesel_factories::Factory::try_insert(self, con)?;
+                    let children = #insert_children;
+                    Ok((model, children))
+                }
+            }
+        });
+
+        quote! {
+            impl #impl_generics #factory #ty_generics #where_clause {
+                #(#methods)*
+            }
+        }
+    }
+
+    fn map_insert_err(&self) -> TokenStream {
+        let factory_name = self.factory_name.to_string();
+        let table_name = self.table_name();
+        quote! {
+            .map_err(|err| diesel_factories::FactoryError::new(#factory_name, #table_name, err))
+        }
+    }
+
+    fn build_factory_trait_impl(&self) -> TokenStream {
+        let build_with = match &self.build_with {
+            Some(build_with) => build_with,
+            None => return quote! {},
+        };
+
+        let factory = &self.factory_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        // The generated `static` is shared by every instantiation of a generic factory. That's
+        // intentional: they all build models for the same table, so sharing the counter keeps
+        // the stub ids unique within the table.
+        //
+        // Composite primary keys get a stub id for each column
+        let stub_id = match &self.id_type {
+            Type::Tuple(tuple) => {
+                let types = tuple.elems.iter();
+                quote! { ( #(STUB_IDS.next::<#types>()),* ) }
+            }
+            _ => quote! { STUB_IDS.next::<Self::Id>() },
+        };
+
+        let association_names = self
+            .associations
+            .iter()
+            .map(|(name, _, _)| name)
+            .collect::<Vec<_>>();
+        let association_ids = self.associations.iter().map(|(name, association_type, _)| {
+            let build = association_type.returning_key("build", "", Vec::new());
+            if association_type.is_optional {
+                quote! {
+                    let #name = self.#name.as_ref().map(|inner| inner.#build);
+                }
+            } else {
+                quote! {
+                    let #name = self.#name.#build;
+                }
+            }
+        });
+
+        // Models are built with the computed values so they match what would be inserted
+        let (mut_self, computed_values) = if self.computed_fields.is_empty() {
+            (quote! { self }, Vec::new())
+        } else {
+            let values = self
+                .computed_fields
+                .iter()
+                .map(|field| {
+                    let name = &field.name;
+                    let value = field.value(&quote! { self });
+                    quote! { self.#name = std::option::Option::Some(#value); }
+                })
+                .collect();
+            (quote! { mut self }, values)
+        };
+
+        quote! {
+            impl #impl_generics diesel_factories::BuildFactory for #factory #ty_generics #where_clause {
+                fn build(#mut_self) -> Self::Model {
+                    static STUB_IDS: diesel_factories::StubIds = diesel_factories::StubIds::new();
+
+                    #(#computed_values)*
+                    let id = #stub_id;
+                    #(#association_ids)*
+                    #build_with(id, self, #(#association_names),*)
+                }
+            }
+        }
+    }
+
+    fn table_name(&self) -> String {
+        self.table
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string())
+            .unwrap_or_default()
+    }
+
+    fn has_callbacks(&self) -> bool {
+        self.before_insert.is_some() || self.after_insert.is_some()
+    }
+
+    fn no_fields(&self) -> bool {
+        self.fields.is_empty()
+            && self.computed_fields.is_empty()
+            && self.associations.is_empty()
+            && self.polymorphic.is_empty()
+    }
+
+    fn has_id_fields(&self) -> bool {
+        self.id_name
+            .iter()
+            .all(|column| self.fields.iter().any(|(name, _)| name == column))
+    }
+
+    fn id_for_model(&self) -> TokenStream {
+        self.id_name.values_of(&quote! { model })
+    }
+
+    /// The column assignments for the foreign key of an association with the id `id`, which is an
+    /// `Option` for optional associations.
+    fn foreign_key_values(
+        &self,
+        foreign_key: &Columns,
+        association_type: &AssociationType,
+        id: TokenStream,
+    ) -> TokenStream {
+        let table_path = &self.table;
+
+        if let Some(column) = foreign_key.single() {
+            return quote! { #table_path::#column.eq(#id) };
+        }
+
+        let values = foreign_key.iter().enumerate().map(|(index, column)| {
+            let index = syn::Index::from(index);
+            if association_type.is_optional {
+                quote! { #table_path::#column.eq(id.as_ref().map(|id| id.#index.clone())) }
+            } else {
+                quote! { #table_path::#column.eq(id.#index) }
+            }
+        });
+
+        quote! {
+            {
+                let id = #id;
+                ( #(#values),* )
+            }
+        }
+    }
+
+    fn field_builder_methods(&self) -> TokenStream {
+        let factory_name = &self.factory_name;
+
+        let has_many_fields = self.has_many.iter().map(|field| (&field.name, &field.ty));
+        let fields = self
+            .fields
+            .iter()
+            .chain(&self.transient_fields)
+            .map(|(name, ty)| (name, ty));
+
+        let methods = fields.chain(has_many_fields).map(|(field_name, ty)| {
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub fn #field_name(mut self, new: impl std::convert::Into<#ty>) -> Self {
+                    self.#field_name = new.into();
+                    self
+                }
+            }
+        });
+
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        let computed_methods = self.computed_fields.iter().map(|field| {
+            let ComputedField { name, ty, .. } = field;
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub fn #name(mut self, new: impl std::convert::Into<#ty>) -> Self {
+                    self.#name = std::option::Option::Some(new.into());
+                    self
+                }
+            }
+        });
+
+        quote! {
+            impl #impl_generics #factory_name #ty_generics #where_clause {
+                #(#methods)*
+                #(#computed_methods)*
+            }
+        }
+    }
+
+    /// Implement `Default` when fields have their defaults set with `default` or `fake`.
+    fn default_impl(&self) -> TokenStream {
+        if self.defaults.iter().all(|(_, default)| default.is_none()) {
+            return quote! {};
+        }
+
+        let factory_name = &self.factory_name;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        let fields = self.defaults.iter().map(|(name, default)| match default {
+            Some(default) => quote! { #name: #default },
+            None => quote! { #name: std::default::Default::default() },
+        });
+
+        quote! {
+            impl #impl_generics std::default::Default for #factory_name #ty_generics #where_clause {
+                fn default() -> Self {
+                    Self {
+                        #(#fields),*
+                    }
+                }
+            }
+        }
+    }
+
+    fn variant_methods(&self) -> TokenStream {
+        if self.variants.is_empty() {
+            return quote! {};
+        }
+
+        let factory_name = &self.factory_name;
+
+        let methods = self.variants.iter().map(|Variant { name, fields }| {
+            let setters = fields.iter().map(|FieldValue { member, expr, .. }| {
+                quote! { .#member(#expr) }
+            });
+
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub fn #name(self) -> Self {
+                    self #(#setters)*
+                }
+            }
+        });
+
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #factory_name #ty_generics #where_clause {
+                #(#methods)*
+            }
+        }
+    }
+
+    fn association_builder_methods(&self) -> TokenStream {
+        let factory_name = &self.factory_name;
+
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+
+        let association_methods = self.associations.iter().map(|(field_name, association_type, _)| {
+            let association_name = format_ident!("{}", field_name.to_string().to_camel_case());
+            let trait_name = format_ident!("Set{}On{}", association_name, factory_name);
+
+            // The association's lifetime is `'static` on factories without a lifetime
+            let association_lifetime = &association_type.lifetime;
+
+            let model_type = &association_type.model_type;
+            let other_factory = &association_type.factory_type;
+
+            let set_impl = |ty: TokenStream, value: TokenStream| {
+                quote! {
+                    impl #impl_generics #trait_name<#ty> for #factory_name #ty_generics #where_clause {
+                        fn #field_name(mut self, t: #ty) -> Self {
+                            self.#field_name = #value;
+                            self
+                        }
+                    }
+                }
+            };
+            let set_association_impl = |ty: TokenStream, association: TokenStream| {
+                if association_type.is_optional {
+                    set_impl(ty, quote! { std::option::Option::Some(#association) })
+                } else {
+                    set_impl(ty, association)
+                }
+            };
+
+            let optional_impls = if association_type.is_optional {
+                let option_model_impl = set_impl(
+                    quote! { std::option::Option<& #association_lifetime #model_type> },
+                    quote! { t.map(diesel_factories::Association::new_model) },
+                );
+                let option_factory_impl = set_impl(
+                    quote! { std::option::Option<#other_factory> },
+                    quote! { t.map(diesel_factories::Association::new_factory) },
+                );
+                quote! {
+                    #option_model_impl
+                    #option_factory_impl
+                }
+            } else {
+                let factory_impl = set_impl(
+                    quote! { #other_factory },
+                    quote! { diesel_factories::Association::new_factory(t) },
+                );
+                quote! { #factory_impl }
+            };
+
+            // Boxed factories can also be set without boxing them first
+            let unboxed_factory_impl = match &association_type.boxed_factory_type {
+                Some(inner) if association_type.is_optional => set_impl(
+                    quote! { std::option::Option<#inner> },
+                    quote! {
+                        t.map(|t| diesel_factories::Association::new_factory(std::boxed::Box::new(t)))
+                    },
+                ),
+                Some(inner) => set_impl(
+                    quote! { #inner },
+                    quote! { diesel_factories::Association::new_factory(std::boxed::Box::new(t)) },
+                ),
+                None => quote! {},
+            };
+
+            let model_impl = set_association_impl(
+                quote! { & #association_lifetime #model_type },
+                quote! { diesel_factories::Association::new_model(t) },
+            );
+            let owned_model_impl = set_association_impl(
+                quote! { #model_type },
+                quote! { diesel_factories::Association::new_owned(t) },
+            );
+            let shared_model_impl = set_association_impl(
+                quote! { std::sync::Arc<#model_type> },
+                quote! { diesel_factories::Association::new_shared_model(t) },
+            );
+            // For shared associations
+            let association_impl = set_association_impl(
+                quote! {
+                    diesel_factories::Association<#association_lifetime, #model_type, #other_factory>
+                },
+                quote! { t },
+            );
+
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub trait #trait_name<T> {
+                    fn #field_name(self, t: T) -> Self;
+                }
+
+                #optional_impls
+                #unboxed_factory_impl
+                #model_impl
+                #owned_model_impl
+                #shared_model_impl
+                #association_impl
+            }
+        }).collect::<TokenStream>();
+
+        let many_to_many_methods = self.many_to_many.iter().map(|field| {
+            let field_name = &field.name;
+            let association_name = format_ident!("{}", field_name.to_string().to_camel_case());
+            let trait_name = format_ident!("Set{}On{}", association_name, factory_name);
+
+            let association_lifetime = &field.association_type.lifetime;
+            let model_type = &field.association_type.model_type;
+            let other_factory = &field.association_type.factory_type;
+
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub trait #trait_name<T> {
+                    fn #field_name(self, t: T) -> Self;
+                }
+
+                impl #impl_generics #trait_name<std::vec::Vec<& #association_lifetime #model_type>> for #factory_name #ty_generics #where_clause {
+                    fn #field_name(mut self, t: std::vec::Vec<& #association_lifetime #model_type>) -> Self {
+                        self.#field_name = t.into_iter().map(diesel_factories::Association::new_model).collect();
+                        self
+                    }
+                }
+
+                impl #impl_generics #trait_name<std::vec::Vec<#other_factory>> for #factory_name #ty_generics #where_clause {
+                    fn #field_name(mut self, t: std::vec::Vec<#other_factory>) -> Self {
+                        self.#field_name = t.into_iter().map(diesel_factories::Association::new_factory).collect();
+                        self
+                    }
+                }
+            }
+        });
+
+        let polymorphic_methods = self.polymorphic.iter().map(|field| {
+            let PolymorphicField {
+                name: field_name,
+                ty: polymorphic_type,
+                lifetime: association_lifetime,
+                ..
+            } = field;
+            let association_name = format_ident!("{}", field_name.to_string().to_camel_case());
+            let trait_name = format_ident!("Set{}On{}", association_name, factory_name);
+
+            let set_impl = |ty: TokenStream, value: TokenStream| {
+                let value = if field.is_optional {
+                    quote! { std::option::Option::Some(#value) }
+                } else {
+                    value
+                };
+                quote! {
+                    impl #impl_generics #trait_name<#ty> for #factory_name #ty_generics #where_clause {
+                        fn #field_name(mut self, t: #ty) -> Self {
+                            self.#field_name = #value;
+                            self
+                        }
+                    }
+                }
+            };
+
+            // Every target can be set with the same kinds of values as a regular association
+            let target_impls = field.targets.iter().map(|target| {
+                let PolymorphicTarget {
+                    model_type,
+                    factory_type,
+                    type_name,
+                } = target;
+                let set_target_impl = |ty: TokenStream, association: TokenStream| {
+                    set_impl(
+                        ty,
+                        quote! {
+                            diesel_factories::Polymorphic::new_association::<#model_type, #factory_type>(
+                                #type_name,
+                                #association,
+                            )
+                        },
+                    )
+                };
+
+                let model_impl = set_target_impl(
+                    quote! { & #association_lifetime #model_type },
+                    quote! { diesel_factories::Association::new_model(t) },
+                );
+                let owned_model_impl = set_target_impl(
+                    quote! { #model_type },
+                    quote! { diesel_factories::Association::new_owned(t) },
+                );
+                let shared_model_impl = set_target_impl(
+                    quote! { std::sync::Arc<#model_type> },
+                    quote! { diesel_factories::Association::new_shared_model(t) },
+                );
+                let factory_impl = set_target_impl(
+                    quote! { #factory_type },
+                    quote! { diesel_factories::Association::new_factory(t) },
+                );
+                let association_impl = set_target_impl(
+                    quote! {
+                        diesel_factories::Association<#association_lifetime, #model_type, #factory_type>
+                    },
+                    quote! { t },
+                );
+
+                quote! {
+                    #model_impl
+                    #owned_model_impl
+                    #shared_model_impl
+                    #factory_impl
+                    #association_impl
+                }
+            });
+
+            let polymorphic_impl = set_impl(quote! { #polymorphic_type }, quote! { t });
+
+            quote! {
+                #[allow(missing_docs, dead_code)]
+                pub trait #trait_name<T> {
+                    fn #field_name(self, t: T) -> Self;
+                }
+
+                #(#target_impls)*
+                #polymorphic_impl
+            }
+        });
+
+        quote! {
+            #association_methods
+            #(#polymorphic_methods)*
+            #(#many_to_many_methods)*
+        }
+    }
+}
+
+/// How the foreign keys of associations are found when inserting a factory.
+#[derive(Debug, Clone, Copy)]
+enum AssociationKeys {
+    /// Insert each association that hasn't been inserted yet.
+    Insert,
+    /// Like `Insert` but with an async connection.
+    InsertAsync,
+    /// Take the keys of associations inserted up front by `insert_each_association_keys`.
+    Inserted,
+}
+
+/// A `HasMany<ChildFactory>` field whose children are inserted after the factory itself.
+#[derive(Debug)]
+struct HasManyField {
+    name: Ident,
+    ty: Type,
+    factory_type: Type,
+    inverse_of: Ident,
+    /// The trait generated for the `inverse_of` association builder method on the child factory.
+    inverse_setter: Path,
+}
+
+/// A `ManyToMany<'a, Model, Factory>` field whose rows are linked to the factory through a join
+/// table after the factory is inserted.
+#[derive(Debug)]
+struct ManyToManyField {
+    name: Ident,
+    association_type: AssociationType,
+    through: Path,
+    owner_key: Ident,
+    target_key: Ident,
+}
+
+/// The last segment of a type path in snake case, such as `country` for `models::Country`.
+fn snake_case_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string().to_snake_case()),
+        _ => None,
+    }
+}
+
+/// The child factory type of `HasMany<ChildFactory>`, if `ty` is a `HasMany`.
+fn has_many_factory_type(ty: &Type) -> Option<Type> {
+    generic_argument_of(ty, "HasMany")
+}
+
+/// The type argument of `ty` if it's the generic type `name` with a single argument, such as `T`
+/// in `Option<T>`.
+fn generic_argument_of(ty: &Type, name: &str) -> Option<Type> {
+    let segment = match ty {
+        Type::Path(type_path) => type_path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != name {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match args.args.first()? {
+            GenericArgument::Type(ty) => Some(ty.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A `Polymorphic<'a, Connection, Id>` field stored in a type column and an id column.
+#[derive(Debug)]
+struct PolymorphicField {
+    name: Ident,
+    /// The `Polymorphic` type, without the `Option` of optional fields.
+    ty: Type,
+    lifetime: Lifetime,
+    is_optional: bool,
+    type_column: Ident,
+    id_column: Ident,
+    targets: Vec<PolymorphicTarget>,
+}
+
+/// A model a `Polymorphic` field can point at, such as `Post: PostFactory<'a>`.
+///
+/// The type column is set to the name of the model unless another name is given with
+/// `Post as "post": PostFactory<'a>`.
+#[derive(Debug)]
+struct PolymorphicTarget {
+    model_type: Type,
+    factory_type: Type,
+    type_name: LitStr,
+}
+
+impl Parse for PolymorphicTarget {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let model_type = input.parse::<Type>()?;
+        let type_name = if input.peek(Token![as]) {
+            input.parse::<Token![as]>()?;
+            input.parse()?
+        } else {
+            let name = match &model_type {
+                Type::Path(type_path) => type_path.path.segments.last().map(|s| &s.ident),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                syn::Error::new(
+                    model_type.span(),
+                    "Can't guess the type name, use `as \"...\"`",
+                )
+            })?;
+            LitStr::new(&name.to_string(), name.span())
+        };
+        input.parse::<Token![:]>()?;
+        let factory_type = input.parse()?;
+        Ok(PolymorphicTarget {
+            model_type,
+            factory_type,
+            type_name,
+        })
+    }
+}
+
+/// The targets of a `Polymorphic` field, such as `(Post: PostFactory<'a>, Photo: PhotoFactory)`.
+#[derive(Debug)]
+struct PolymorphicTargets(Vec<PolymorphicTarget>);
+
+impl Parse for PolymorphicTargets {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        let parens = syn::parenthesized!(content in input);
+        let targets = Punctuated::<PolymorphicTarget, Token![,]>::parse_terminated(&content)?;
+        if targets.is_empty() {
+            return Err(syn::Error::new(parens.span, "Expected at least one target"));
+        }
+        Ok(PolymorphicTargets(targets.into_iter().collect()))
+    }
+}
 
-            if let Ok(association_type) = AssociationType::new(field_ty) {
-                let foreign_key_name =
-                    if let Some(attr) = field_attr::Factory::try_from_attributes(&field.attrs)? {
-                        attr.foreign_key_name
-                    } else {
-                        format_ident!("{}_{}", name, id_name)
-                    };
+/// The `Polymorphic` type of `ty` and whether it's wrapped in an `Option`.
+fn polymorphic_type(ty: &Type) -> Option<(Type, bool)> {
+    let (ty, is_optional) = match generic_argument_of(ty, "Option") {
+        Some(inner) => (inner, true),
+        None => (ty.clone(), false),
+    };
+    match &ty {
+        Type::Path(type_path) if type_path.path.segments.last()?.ident == "Polymorphic" => {
+            Some((ty, is_optional))
+        }
+        _ => None,
+    }
+}
 
-                associations.push((name, association_type, foreign_key_name));
-            } else {
-                if field_attr::Factory::from_attributes(&field.attrs).is_ok() {
-                    return Err(syn::Error::new(
-                        field_span,
-                        "`#[factory]` attributes are only allowed on association fields",
-                    ));
-                }
+/// A field declared with `#[factory(computed = |f| ...)]`.
+#[derive(Debug)]
+struct ComputedField {
+    name: Ident,
+    /// The type of the column, without the `Option` of the field.
+    ty: Type,
+    computed: syn::Expr,
+}
 
-                fields.push((name, field.ty));
+impl ComputedField {
+    /// The value of the field on `factory`, computing it unless it was set with the builder
+    /// method.
+    fn value(&self, factory: &TokenStream) -> TokenStream {
+        let ComputedField { name, computed, .. } = self;
+        quote! {
+            match &#factory.#name {
+                std::option::Option::Some(value) => std::clone::Clone::clone(value),
+                std::option::Option::None => diesel_factories::compute(&#factory, #computed),
             }
         }
+    }
+}
 
-        // parse generic lifetime
-        let generics_span = generics.span();
-        let mut generics_iter = generics.params.into_iter();
-        let lifetime = match generics_iter.next() {
-            Some(inner) => match inner {
-                syn::GenericParam::Lifetime(lt_def) => {
-                    if !lt_def.bounds.is_empty() {
-                        return Err(syn::Error::new(lt_def.span(), "Unexpected lifetime bounds"));
-                    }
+/// One or more column names, such as `id` or `(user_id, team_id)` for composite keys.
+#[derive(Debug)]
+struct Columns(Vec<Ident>);
 
-                    Some(lt_def.lifetime)
-                }
-                _ => {
-                    return Err(syn::Error::new(
-                        generics_span,
-                        "Expected a single generic lifetime argument",
-                    ));
-                }
-            },
-            None => None,
-        };
+impl Columns {
+    fn single(&self) -> Option<&Ident> {
+        match self.0.as_slice() {
+            [column] => Some(column),
+            _ => None,
+        }
+    }
+
+    fn is_composite(&self) -> bool {
+        self.single().is_none()
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, Ident> {
+        self.0.iter()
+    }
 
-        if let Some(arg) = generics_iter.next() {
-            return Err(syn::Error::new(arg.span(), "Unexpected generic argument"));
+    /// The values of the columns on `model`, as a tuple for composite keys.
+    fn values_of(&self, model: &TokenStream) -> TokenStream {
+        match self.single() {
+            Some(column) => quote! { #model.#column.clone() },
+            None => {
+                let columns = self.iter();
+                quote! { ( #(#model.#columns.clone()),* ) }
+            }
         }
+    }
+}
 
-        Ok(Input {
-            model,
-            table,
-            connection,
-            id_type,
-            id_name,
-            factory_name,
-            fields,
-            associations,
-            lifetime,
-        })
+impl From<Ident> for Columns {
+    fn from(column: Ident) -> Self {
+        Columns(vec![column])
     }
 }
 
-impl ToTokens for Input {
-    fn to_tokens(&self, tokens: &mut TokenStream) {
-        tokens.extend(self.factory_trait_impl());
-        tokens.extend(self.field_builder_methods());
-        tokens.extend(self.association_builder_methods());
+impl Parse for Columns {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if !input.peek(syn::token::Paren) {
+            return input.parse::<Ident>().map(Columns::from);
+        }
+
+        let content;
+        let parens = syn::parenthesized!(content in input);
+        let columns = Punctuated::<Ident, Token![,]>::parse_terminated(&content)?;
+        if columns.is_empty() {
+            return Err(syn::Error::new(parens.span, "Expected at least one column"));
+        }
+        Ok(Columns(columns.into_iter().collect()))
     }
 }
 
-impl Input {
-    fn factory_trait_impl(&self) -> TokenStream {
-        let factory = &self.factory_name;
-        let lifetime = &self.lifetime;
-        let model_type = &self.model;
-        let id_type = &self.id_type;
-        let connection_type = &self.connection;
-        let table_path = &self.table;
-        let id_name = &self.id_name;
+/// A named set of field values declared with `#[factory(variant(admin = { role: "admin" }))]`.
+#[derive(Debug)]
+struct Variant {
+    name: Ident,
+    fields: Punctuated<FieldValue, Token![,]>,
+}
 
-        let insert_code = if self.no_fields() {
-            quote! {
-                diesel::insert_into(#table_path::table)
-                    .default_values()
-                    .get_result::<Self::Model>(con)
-                    .expect("Insert of factory failed")
-            }
-        } else {
-            let values = self.fields.iter().map(|(name, _)| {
-                quote! { #table_path::#name.eq(&self.#name) }
-            });
-            let values = values.chain(self.associations.iter().map(
-                |(name, association_type, foreign_key_field)| {
-                    if association_type.is_optional {
-                        quote! {
-                            {
-                                let value = self.#name.map(|inner| {
-                                    inner.insert_returning_id(con)
-                                });
-                                #table_path::#foreign_key_field.eq(value)
-                            }
-                        }
-                    } else {
-                        quote! {
-                            #table_path::#foreign_key_field.eq(self.#name.insert_returning_id(con))
-                        }
-                    }
-                },
-            ));
+impl Parse for Variant {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let content;
+        syn::braced!(content in input);
+        let fields = Punctuated::parse_terminated(&content)?;
+        Ok(Variant { name, fields })
+    }
+}
 
-            quote! {
-                let values = ( #(#values),* );
-                diesel::insert_into(#table_path::table)
-                    .values(values)
-                    .get_result::<Self::Model>(con)
-                    .expect("Insert of factory failed")
-            }
+/// Remove the `variant(...)` arguments from the struct's `#[factory]` attributes and parse them.
+///
+/// They aren't `key = value` pairs, so they have to be gone before the rest of the attribute is
+/// parsed.
+fn take_variants(attrs: &mut [Attribute]) -> syn::Result<Vec<Variant>> {
+    let mut variants = Vec::new();
+
+    for attr in attrs
+        .iter_mut()
+        .filter(|attr| attr.path.is_ident("factory"))
+    {
+        let group = match attr.tokens.clone().into_iter().next() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => group,
+            _ => continue,
         };
 
-        quote! {
-            impl <#lifetime> diesel_factories::Factory for #factory <#lifetime> {
-                type Model = #model_type;
-                type Id = #id_type;
-                type Connection = #connection_type;
+        let mut args = vec![Vec::new()];
+        for token in group.stream() {
+            match token {
+                TokenTree::Punct(punct) if punct.as_char() == ',' => args.push(Vec::new()),
+                token => args.last_mut().unwrap().push(token),
+            }
+        }
 
-                fn insert(self, con: &Self::Connection) -> Self::Model {
-                    use diesel::prelude::*;
-                    #insert_code
+        let mut rest = TokenStream::new();
+        for arg in args {
+            match arg.as_slice() {
+                [] => {}
+                [TokenTree::Ident(ident), TokenTree::Group(content)]
+                    if ident == "variant" && content.delimiter() == Delimiter::Parenthesis =>
+                {
+                    let parser = Punctuated::<Variant, Token![,]>::parse_terminated;
+                    variants.extend(syn::parse::Parser::parse2(parser, content.stream())?);
                 }
-
-                fn id_for_model(model: &Self::Model) -> &Self::Id {
-                    &model.#id_name
+                _ => {
+                    rest.extend(arg);
+                    rest.extend(quote! { , });
                 }
             }
         }
-    }
 
-    fn no_fields(&self) -> bool {
-        self.fields.is_empty() && self.associations.is_empty()
+        let mut group = Group::new(Delimiter::Parenthesis, rest);
+        group.set_span(attr.tokens.span());
+        attr.tokens = TokenTree::Group(group).into();
     }
 
-    fn field_builder_methods(&self) -> TokenStream {
-        let factory_name = &self.factory_name;
-
-        let methods = self.fields.iter().map(|(field_name, ty)| {
-            quote! {
-                #[allow(missing_docs, dead_code)]
-                pub fn #field_name(mut self, new: impl std::convert::Into<#ty>) -> Self {
-                    self.#field_name = new.into();
-                    self
-                }
-            }
-        });
+    Ok(variants)
+}
 
-        let lifetime = &self.lifetime;
+/// The database backend a factory inserts into, which decides how inserted rows are loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Pg,
+    Sqlite,
+    Mysql,
+}
 
-        quote! {
-            impl <#lifetime> #factory_name <#lifetime> {
-                #(#methods)*
-            }
+impl Parse for Backend {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse::<Ident>()?;
+        match name.to_string().as_str() {
+            "pg" => Ok(Backend::Pg),
+            "sqlite" => Ok(Backend::Sqlite),
+            "mysql" => Ok(Backend::Mysql),
+            _ => Err(syn::Error::new(
+                name.span(),
+                "Unknown backend. Expected `pg`, `sqlite` or `mysql`",
+            )),
         }
     }
+}
 
-    fn association_builder_methods(&self) -> TokenStream {
-        let factory_name = &self.factory_name;
-
-        self.associations.iter().map(|(field_name, association_type, _)| {
-            let association_name = format_ident!("{}", field_name.to_string().to_camel_case());
-            let trait_name = format_ident!("Set{}On{}", association_name, factory_name);
-
-            let lifetime = &association_type.lifetime;
-
-            let model_type = &association_type.model_type;
-            let other_factory = &association_type.factory_type;
-
-            let model_impl = if association_type.is_optional {
-                quote! {
-                    impl<#lifetime> #trait_name<std::option::Option<& #lifetime #model_type>> for #factory_name<#lifetime> {
-                        fn #field_name(mut self, t: std::option::Option<& #lifetime #model_type>) -> Self {
-                            self.#field_name = t.map(diesel_factories::Association::new_model);
-                            self
-                        }
-                    }
-                }
-            } else {
-                quote! {
-                    impl<#lifetime> #trait_name<& #lifetime #model_type> for #factory_name<#lifetime> {
-                        fn #field_name(mut self, t: & #lifetime #model_type) -> Self {
-                            self.#field_name = diesel_factories::Association::new_model(t);
-                            self
-                        }
-                    }
-                }
-            };
+impl Backend {
+    /// Guess the backend from the name of the connection type, such as `SqliteConnection`. Used
+    /// when the factory doesn't set `backend`.
+    fn from_connection(connection: &Type) -> Self {
+        let name = match connection {
+            Type::Path(type_path) => type_path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident.to_string()),
+            _ => None,
+        };
 
-            let factory_impl = if association_type.is_optional {
-                quote! {
-                    impl<#lifetime> #trait_name<std::option::Option<#other_factory>> for #factory_name<#lifetime> {
-                        fn #field_name(mut self, t: std::option::Option<#other_factory>) -> Self {
-                            self.#field_name = t.map(diesel_factories::Association::new_factory);
-                            self
-                        }
-                    }
-                }
-            } else {
-                quote! {
-                    impl<#lifetime> #trait_name<#other_factory> for #factory_name<#lifetime> {
-                        fn #field_name(mut self, t: #other_factory) -> Self {
-                            self.#field_name = diesel_factories::Association::new_factory(t);
-                            self
-                        }
-                    }
-                }
-            };
+        match name.as_deref() {
+            Some("SqliteConnection") => Backend::Sqlite,
+            Some("MysqlConnection") | Some("AsyncMysqlConnection") => Backend::Mysql,
+            // diesel-async's wrapper for blocking connections such as `SqliteConnection`
+            Some("SyncConnectionWrapper") => {
+                generic_argument_of(connection, "SyncConnectionWrapper")
+                    .map(|inner| Backend::from_connection(&inner))
+                    .unwrap_or(Backend::Pg)
+            }
+            _ => Backend::Pg,
+        }
+    }
 
-            quote! {
-                #[allow(missing_docs, dead_code)]
-                pub trait #trait_name<T> {
-                    fn #field_name(self, t: T) -> Self;
-                }
+    fn supports_returning(self) -> bool {
+        match self {
+            Backend::Pg => true,
+            Backend::Sqlite | Backend::Mysql => false,
+        }
+    }
 
-                #model_impl
-                #factory_impl
-            }
-        }).collect()
+    /// SQL for getting the primary key generated by the last insert, for backends without
+    /// `RETURNING`.
+    fn last_insert_id_sql(self) -> Option<&'static str> {
+        match self {
+            Backend::Pg => None,
+            Backend::Sqlite => Some("last_insert_rowid()"),
+            Backend::Mysql => Some("LAST_INSERT_ID()"),
+        }
     }
 }
 
@@ -341,11 +1986,23 @@ struct AssociationType {
     lifetime: Lifetime,
     model_type: Type,
     factory_type: Type,
+    /// The factory inside the `Box` for boxed factories such as `Box<CategoryFactory<'a>>`.
+    boxed_factory_type: Option<Type>,
     is_optional: bool,
+    /// The columns of the model the foreign key references, when it isn't the primary key.
+    references: Option<Columns>,
 }
 
 impl AssociationType {
     fn new(ty: Type) -> syn::Result<Self> {
+        Self::with_name(ty.clone(), "Association")
+            .or_else(|err| Self::with_name(ty, "OwnedAssociation").map_err(|_| err))
+    }
+
+    /// Parse a type like `Association<'a, Model, Factory>` where `Association` is `type_name`.
+    ///
+    /// The lifetime is `'static` when it's left out, as in `OwnedAssociation<Model, Factory>`.
+    fn with_name(ty: Type, type_name: &str) -> syn::Result<Self> {
         let type_path = match ty {
             Type::Path(ty) => ty,
             _ => return Err(syn::Error::new(ty.span(), "Expected type path")),
@@ -382,15 +2039,15 @@ impl AssociationType {
         let path_segment = segments_iter
             .next()
             .ok_or_else(|| syn::Error::new(segments_span, "Type path too short"))?;
-        let arguments = if path_segment.ident == "Association" {
+        let arguments = if path_segment.ident == type_name {
             path_segment.arguments
         } else {
             return Err(syn::Error::new(
                 path_segment.span(),
                 format!(
-                    "Unexpected name `{}`. Expected `Association` or `diesel_factories::Association`",
-                    path_segment.ident,
-                )
+                    "Unexpected name `{}`. Expected `{}` or `diesel_factories::{}`",
+                    path_segment.ident, type_name, type_name,
+                ),
             ));
         };
 
@@ -415,18 +2072,15 @@ impl AssociationType {
         }
 
         let args_span = arguments.span();
-        let mut args_iter = arguments.args.into_iter();
+        let mut args_iter = arguments.args.into_iter().peekable();
 
-        let lifetime = match args_iter.next() {
-            Some(inner) => match inner {
-                syn::GenericArgument::Lifetime(lt) => lt,
-                _ => {
-                    return Err(syn::Error::new(
-                        args_span,
-                        "Expected generic lifetime argument",
-                    ));
-                }
-            },
+        let lifetime = match args_iter.peek() {
+            Some(syn::GenericArgument::Lifetime(lt)) => {
+                let lt = lt.clone();
+                args_iter.next();
+                lt
+            }
+            Some(_) => Lifetime::new("'static", Span::call_site()),
             None => {
                 return Err(syn::Error::new(args_span, "Missing generic type arguments"));
             }
@@ -460,14 +2114,35 @@ impl AssociationType {
             return Err(syn::Error::new(next.span(), "Too many generic arguments"));
         }
 
+        // Self-referential associations need the factory boxed
+        let boxed_factory_type = generic_argument_of(&factory_type, "Box");
+
         Ok(AssociationType {
             span: whole_span,
             lifetime,
             model_type,
             factory_type,
+            boxed_factory_type,
             is_optional,
+            references: None,
         })
     }
+
+    /// Call the `Association` method returning the id of the association, such as
+    /// `try_insert_returning_id(con)` for `try_insert`, or the `_key` version of it returning the
+    /// `references` columns.
+    fn returning_key(&self, prefix: &str, suffix: &str, mut args: Vec<TokenStream>) -> TokenStream {
+        let method = match &self.references {
+            Some(references) => {
+                let model_type = &self.model_type;
+                let key = references.values_of(&quote! { model });
+                args.push(quote! { |model: &#model_type| #key });
+                format_ident!("{}_returning_key{}", prefix, suffix)
+            }
+            None => format_ident!("{}_returning_id{}", prefix, suffix),
+        };
+        quote! { #method(#(#args),*) }
+    }
 }
 
 fn peel_option(
@@ -623,6 +2298,20 @@ mod test {
         assert_eq!(ty.is_optional, true);
     }
 
+    #[test]
+    fn is_association_type_true_without_lifetime() {
+        let tokens = quote! { diesel_factories::OwnedAssociation<Country, CountryFactory> };
+        let ty = syn::parse2::<AssociationType>(tokens).unwrap();
+
+        assert_eq!(ty.lifetime.ident, "static");
+        assert_eq!(ty.model_type, syn::parse2(quote! { Country }).unwrap());
+        assert_eq!(
+            ty.factory_type,
+            syn::parse2(quote! { CountryFactory }).unwrap()
+        );
+        assert_eq!(ty.is_optional, false);
+    }
+
     #[test]
     fn is_association_type_false() {
         let tokens = quote! { Country };
@@ -643,4 +2332,146 @@ mod test {
         let ty = syn::parse2::<AssociationType>(tokens);
         assert!(ty.is_err());
     }
+
+    #[test]
+    fn backend_from_connection() {
+        let backend = |tokens| Backend::from_connection(&syn::parse2(tokens).unwrap());
+
+        assert_eq!(backend(quote! { diesel::pg::PgConnection }), Backend::Pg);
+        assert_eq!(
+            backend(quote! { diesel::sqlite::SqliteConnection }),
+            Backend::Sqlite
+        );
+        assert_eq!(backend(quote! { SqliteConnection }), Backend::Sqlite);
+        assert_eq!(
+            backend(quote! { diesel::mysql::MysqlConnection }),
+            Backend::Mysql
+        );
+        assert_eq!(
+            backend(quote! { diesel_async::AsyncMysqlConnection }),
+            Backend::Mysql
+        );
+        assert_eq!(
+            backend(quote! { SyncConnectionWrapper<SqliteConnection> }),
+            Backend::Sqlite
+        );
+        assert_eq!(
+            backend(quote! { diesel_async::AsyncPgConnection }),
+            Backend::Pg
+        );
+    }
+
+    #[test]
+    fn backend_from_attribute() {
+        assert_eq!(syn::parse2::<Backend>(quote! { pg }).unwrap(), Backend::Pg);
+        assert_eq!(
+            syn::parse2::<Backend>(quote! { sqlite }).unwrap(),
+            Backend::Sqlite
+        );
+        assert_eq!(
+            syn::parse2::<Backend>(quote! { mysql }).unwrap(),
+            Backend::Mysql
+        );
+        assert!(syn::parse2::<Backend>(quote! { oracle }).is_err());
+    }
+
+    #[test]
+    fn columns_single_and_composite() {
+        let single = syn::parse2::<Columns>(quote! { identity }).unwrap();
+        assert_eq!(single.single().unwrap(), "identity");
+
+        let composite = syn::parse2::<Columns>(quote! { (user_id, team_id) }).unwrap();
+        assert!(composite.is_composite());
+        assert_eq!(
+            composite.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec!["user_id", "team_id"]
+        );
+
+        assert!(syn::parse2::<Columns>(quote! { () }).is_err());
+    }
+
+    #[test]
+    fn polymorphic_targets_with_type_names() {
+        let targets = syn::parse2::<PolymorphicTargets>(quote! {
+            (models::Post: PostFactory<'a>, Photo as "photo": PhotoFactory)
+        })
+        .unwrap();
+        assert_eq!(
+            targets
+                .0
+                .iter()
+                .map(|target| target.type_name.value())
+                .collect::<Vec<_>>(),
+            vec!["Post", "photo"]
+        );
+
+        assert!(syn::parse2::<PolymorphicTargets>(quote! { () }).is_err());
+
+        let ty = syn::parse2::<Type>(quote! { Option<Polymorphic<'a, PgConnection>> }).unwrap();
+        assert!(polymorphic_type(&ty).unwrap().1);
+    }
+
+    #[test]
+    fn take_variants_from_attributes() {
+        let item = syn::parse2::<ItemStruct>(quote! {
+            #[factory(
+                model = User,
+                variant(admin = { role: "admin", verified: true }),
+                table = crate::schema::users,
+                variant(guest = { role: "guest" }),
+            )]
+            struct UserFactory {}
+        })
+        .unwrap();
+        let mut attrs = item.attrs;
+
+        let variants = take_variants(&mut attrs).unwrap();
+
+        assert_eq!(
+            variants
+                .iter()
+                .map(|v| v.name.to_string())
+                .collect::<Vec<_>>(),
+            vec!["admin", "guest"]
+        );
+        assert_eq!(variants[0].fields.len(), 2);
+        assert_eq!(
+            attrs[0].tokens.to_string(),
+            quote! { (model = User, table = crate::schema::users,) }.to_string()
+        );
+    }
+
+    #[test]
+    fn has_many_factory_type_of_field() {
+        let ty = has_many_factory_type(&syn::parse2(quote! { HasMany<CityFactory<'a>> }).unwrap());
+        assert_eq!(ty, Some(syn::parse2(quote! { CityFactory<'a> }).unwrap()));
+
+        let ty = has_many_factory_type(&syn::parse2(quote! { Vec<CityFactory<'a>> }).unwrap());
+        assert_eq!(ty, None);
+    }
+
+    #[test]
+    fn generic_argument_of_option() {
+        let ty = generic_argument_of(&syn::parse2(quote! { Option<String> }).unwrap(), "Option");
+        assert_eq!(ty, Some(syn::parse2(quote! { String }).unwrap()));
+
+        let ty = generic_argument_of(&syn::parse2(quote! { String }).unwrap(), "Option");
+        assert_eq!(ty, None);
+    }
+
+    #[test]
+    fn is_many_to_many_type() {
+        let tokens = quote! { diesel_factories::ManyToMany<'a, Team, TeamFactory> };
+        let ty = AssociationType::with_name(syn::parse2(tokens).unwrap(), "ManyToMany").unwrap();
+
+        assert_eq!(ty.lifetime.ident, "a");
+        assert_eq!(ty.model_type, syn::parse2(quote! { Team }).unwrap());
+        assert_eq!(
+            ty.factory_type,
+            syn::parse2(quote! { TeamFactory }).unwrap()
+        );
+
+        let tokens = quote! { ManyToMany<'a, Team, TeamFactory> };
+        assert!(AssociationType::new(syn::parse2(tokens).unwrap()).is_err());
+    }
 }
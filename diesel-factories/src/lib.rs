@@ -200,6 +200,162 @@
 //! }
 //! ```
 //!
+//! ## Diesel 2
+//!
+//! Diesel 1 is used by default. To use Diesel 2 instead disable the default features and enable
+//! `diesel2`:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! diesel-factories = { version = "2", default-features = false, features = ["diesel2"] }
+//! ```
+//!
+//! Everything works the same except that connections are passed as `&mut` since Diesel 2 requires
+//! that for running queries:
+//!
+//! ```ignore
+//! let city = CityFactory::default().insert(&mut con);
+//! ```
+//!
+//! ## Async factories
+//!
+//! With the `async` feature, which uses Diesel 2, factories can be inserted using an async
+//! connection from [diesel-async][]. Set `async_connection` and your factory will implement
+//! [`AsyncFactory`]:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! diesel-factories = { version = "2", default-features = false, features = ["async"] }
+//! ```
+//!
+//! ```ignore
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//!     async_connection = diesel_async::AsyncPgConnection,
+//! )]
+//! struct CityFactory<'a> {
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! let city = CityFactory::default().insert(&mut con).await;
+//! ```
+//!
+//! Associations are inserted with the async connection as well, so their factories must also
+//! implement `AsyncFactory`. Factories that only set `async_connection` don't implement
+//! [`Factory`], so they can't use `build_with` either. Set both `connection` and
+//! `async_connection` to get both.
+//!
+//! Async factories only support Postgres, since the inserted rows are loaded with `RETURNING`.
+//!
+//! [diesel-async]: https://docs.rs/diesel-async
+//! [`AsyncFactory`]: trait.AsyncFactory.html
+//!
+//! ## SQLite and MySQL
+//!
+//! Factories with `connection = diesel::sqlite::SqliteConnection` or
+//! `connection = diesel::mysql::MysqlConnection` work with SQLite and MySQL databases, which don't
+//! support `INSERT ... RETURNING`. The row is inserted first and then loaded again using
+//! `last_insert_rowid()` or `LAST_INSERT_ID()`, so the primary key must be generated by the
+//! database, such as an `INTEGER PRIMARY KEY` or `AUTO_INCREMENT` column.
+//!
+//! Factories without fields are inserted with `default_values()`, which Diesel writes as
+//! `() VALUES ()` on MySQL.
+//!
+//! Tables where the primary key is supplied by the application are supported by having a field
+//! for the primary key on the factory. The row is then loaded again using that value. The
+//! examples here use SQLite, but MySQL factories look the same with
+//! `diesel::mysql::MysqlConnection` and `backend = mysql`:
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate diesel;
+//! #
+//! # use diesel::prelude::*;
+//! # use diesel_factories::Factory;
+//! #
+//! # mod schema {
+//! #     table! {
+//! #         currencies (code) {
+//! #             code -> Text,
+//! #             name -> Text,
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Currency {
+//! #     pub code: String,
+//! #     pub name: String,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = Currency,
+//!     table = crate::schema::currencies,
+//!     connection = diesel::sqlite::SqliteConnection,
+//!     id = String,
+//!     id_name = code,
+//! )]
+//! struct CurrencyFactory {
+//!     pub code: String,
+//!     pub name: String,
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! [`Factory::insert_many`] and [`Factory::insert_each`] insert one row at a time on these
+//! databases rather than using a single `INSERT` statement.
+//!
+//! The backend is guessed from the last segment of the connection type's path, so
+//! `SqliteConnection` means SQLite, `MysqlConnection` means MySQL and anything else means
+//! Postgres. Set `backend = pg`, `backend = sqlite` or `backend = mysql` when the connection type
+//! is a type alias or a wrapper whose name doesn't match:
+//!
+//! ```
+//! # #[macro_use]
+//! # extern crate diesel;
+//! #
+//! # use diesel::prelude::*;
+//! # use diesel_factories::Factory;
+//! #
+//! # mod schema {
+//! #     table! {
+//! #         countries (id) {
+//! #             id -> Integer,
+//! #             name -> Text,
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Country {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! # }
+//! #
+//! type Db = diesel::sqlite::SqliteConnection;
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = Country,
+//!     table = crate::schema::countries,
+//!     connection = Db,
+//!     backend = sqlite,
+//! )]
+//! struct CountryFactory {
+//!     pub name: String,
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! SQLite support is tested against an in-memory database. MySQL support is only type checked,
+//! it isn't run against a MySQL database.
+//!
 //! ## `#[derive(Factory)]`
 //!
 //! ### Attributes
@@ -210,15 +366,56 @@
 //! |---|---|---|---|
 //! | `model` | Model type your factory inserts | `City` | None, required |
 //! | `table` | Table your model belongs to | `crate::schema::cities` | None, required |
-//! | `connection` | The connection type your app uses | `MysqlConnection` | `diesel::pg::PgConnection` |
-//! | `id` | The type of your table's primary key | `i64` | `i32` |
-//! | `id_name` | The name of your table's primary key column | `identity` | `id` |
+//! | `connection` | The connection type your app uses | `diesel::sqlite::SqliteConnection` | `diesel::pg::PgConnection` |
+//! | `async_connection` | The async connection type your app uses. See [below](#async-factories) | `diesel_async::AsyncPgConnection` | None |
+//! | `backend` | The database backend, when it can't be guessed from the connection type. See [below](#sqlite-and-mysql) | `mysql` | Guessed from the connection type |
+//! | `id` | The type of your table's primary key. See [below](#composite-primary-keys) | `i64` | `i32` |
+//! | `id_name` | The name of your table's primary key column, or a tuple of columns | `identity` | `id` |
+//! | `build_with` | Function that makes a model without inserting it. See [below](#building-models-without-a-database) | `build_city` | None |
+//! | `before_insert` | Function called with the factory before it's inserted. See [below](#insert-callbacks) | `normalize_user` | None |
+//! | `after_insert` | Function called with the inserted model. See [below](#insert-callbacks) | `create_profile` | None |
+//! | `variant` | Named sets of field values, generated as builder methods. See [below](#variants) | `variant(admin = { role: "admin" })` | None |
+//!
+//! These attributes are available on any field inside `#[factory(...)]`.
+//!
+//! | Name | Description | Example | Default |
+//! |---|---|---|---|
+//! | `transient` | Field only used by the factory itself and not inserted. See [below](#transient-fields) | `#[factory(transient)]` | Not transient |
+//! | `default` | Default value of the field. See [below](#field-defaults-and-fake-data) | `"Bob".into()` | None |
+//! | `computed` | Closure computing the value from the factory when inserted. See [below](#computed-fields) | `\|f\| f.name.to_lowercase()` | None |
+//! | `fake` | Generator from [`fake`](fake/index.html) making the default value. Requires the `fake` feature | `"email"` | None |
 //!
 //! These attributes are available on association fields inside `#[factory(...)]`.
 //!
 //! | Name | Description | Example | Default |
 //! |---|---|---|---|
-//! | `foreign_key_name` | Name of the foreign key column on your model | `country_identity` | `{association_name}_id` |
+//! | `foreign_key_name` | Name of the foreign key column on your model, or a tuple of columns for composite keys | `country_identity` | `{association_name}_id` |
+//! | `references` | Column of the associated model the foreign key references, when it isn't the primary key. See [below](#foreign-keys-referencing-other-columns) | `code` | The primary key |
+//!
+//! These attributes are available on [`HasMany`](#has-many-associations) fields inside
+//! `#[factory(...)]`.
+//!
+//! | Name | Description | Example | Default |
+//! |---|---|---|---|
+//! | `inverse_of` | Name of the association on the child factory pointing back to this factory | `home_country` | The model name in snake case |
+//!
+//! These attributes are available on [`ManyToMany`](#many-to-many-associations) fields inside
+//! `#[factory(...)]`.
+//!
+//! | Name | Description | Example | Default |
+//! |---|---|---|---|
+//! | `through` | The join table | `crate::schema::team_memberships` | None, required |
+//! | `owner_key` | Column in the join table referencing this factory's model | `member_id` | `{model_name}_id` |
+//! | `target_key` | Column in the join table referencing the associated model | `group_id` | `{associated_model_name}_id` |
+//!
+//! These attributes are available on [`Polymorphic`](#polymorphic-associations) fields inside
+//! `#[factory(...)]`.
+//!
+//! | Name | Description | Example | Default |
+//! |---|---|---|---|
+//! | `targets` | The models the association can point at and their factories | `(Post: PostFactory<'a>, Photo as "photo": PhotoFactory)` | None, required |
+//! | `type_column` | Column with the type of the associated model | `kind` | `{association_name}_type` |
+//! | `foreign_key_name` | Column with the id of the associated model | `target_id` | `{association_name}_id` |
 //!
 //! ### Builder methods
 //!
@@ -338,6 +535,83 @@
 //!
 //! This should prevent bugs where you have multiple factory instances sharing some association that you mutate halfway through a test.
 //!
+//! ### Factories without lifetimes
+//!
+//! Associations can also be set to an owned `Country` or an `Arc<Country>`. Factories that only
+//! use those can declare their associations as [`OwnedAssociation`], which is an `Association`
+//! with a `'static` lifetime. Such factories don't need a lifetime parameter, which makes them
+//! easy to store in fixtures or return from helper functions:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! use diesel_factories::OwnedAssociation;
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//! )]
+//! struct CityFactory {
+//!     pub name: String,
+//!     pub country: OwnedAssociation<Country, CountryFactory>,
+//! }
+//! #
+//! # impl Default for CityFactory {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: String::new(), country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! fn city_in(country: Country) -> CityFactory {
+//!     CityFactory::default().country(country)
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! Factories can also have more than one lifetime, for example to borrow string fields for less
+//! time than the associated models:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct User {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub email: String,
+//! #     pub age: i32,
+//! #     pub country_id: i32,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = User, table = crate::schema::users)]
+//! struct UserFactory<'a, 'm> {
+//!     pub name: &'a str,
+//!     pub country: Association<'m, Country, CountryFactory>,
+//! }
+//! #
+//! # impl<'a, 'm> Default for UserFactory<'a, 'm> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: "Bob", country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! fn insert_user(name: &str, country: &Country, con: &PgConnection) -> User {
+//!     let name = name.to_uppercase();
+//!     UserFactory::default().name(name.as_str()).country(country).insert(con)
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
 //! ### Optional associations
 //!
 //! If your model has a nullable association you can do this:
@@ -386,9 +660,11 @@
 //! # }
 //! ```
 //!
-//! ### Customizing foreign key names
+//! ### Transient fields
 //!
-//! You can customize the name of the foreign key for your associations like so
+//! Fields marked with `#[factory(transient)]` get builder methods like other fields but aren't
+//! inserted, so they don't need a column in the table. They're useful for settings that only
+//! affect how the factory makes its model, such as a locale read by a `build_with` function:
 //!
 //! ```
 //! # #![allow(unused_imports)]
@@ -400,125 +676,1993 @@
 //!     table = crate::schema::cities,
 //! )]
 //! struct CityFactory<'a> {
-//!     #[factory(foreign_key_name = country_id)]
+//!     pub name: String,
 //!     pub country: Association<'a, Country, CountryFactory>,
-//! #   pub name: String,
+//!     #[factory(transient)]
+//!     pub locale: &'static str,
 //! }
 //! #
 //! # impl<'a> Default for CityFactory<'a> {
 //! #     fn default() -> Self {
-//! #         unimplemented!()
+//! #         Self {
+//! #             name: String::new(), country: Association::default(), locale: "da",
+//! #         }
+//! #     }
+//! # }
+//!
+//! # fn main() {
+//! CityFactory::default().locale("sv");
+//! # }
+//! ```
+//!
+//! ### Insert callbacks
+//!
+//! `before_insert` and `after_insert` name functions that run around the insert, whether the
+//! factory is inserted directly or as an association of another factory. `before_insert` gets the
+//! factory before anything is inserted and may change it. `after_insert` gets the inserted model
+//! and the factory, including transient fields, once the row and its `HasMany` and `ManyToMany`
+//! associations are inserted:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//!     before_insert = capitalize_name,
+//!     after_insert = log_city,
+//! )]
+//! struct CityFactory<'a> {
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//!     #[factory(transient)]
+//!     pub log: bool,
+//! }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: String::new(), country: Association::default(), log: false,
+//! #         }
 //! #     }
 //! # }
+//!
+//! fn capitalize_name(factory: &mut CityFactory<'_>, _con: &PgConnection) -> QueryResult<()> {
+//!     factory.name = factory.name.to_uppercase();
+//!     Ok(())
+//! }
+//!
+//! fn log_city(city: &City, factory: &CityFactory<'_>, _con: &PgConnection) -> QueryResult<()> {
+//!     if factory.log {
+//!         println!("Inserted {}", city.name);
+//!     }
+//!     Ok(())
+//! }
 //! #
 //! # fn main() {}
 //! ```
-#![doc(html_root_url = "https://docs.rs/diesel-factories/2.0.0")]
-#![deny(
-    mutable_borrow_reservation_conflict,
-    missing_docs,
-    missing_debug_implementations,
-    missing_copy_implementations,
-    trivial_casts,
-    trivial_numeric_casts,
-    unsafe_code,
-    unstable_features,
-    unused_import_braces,
-    unused_qualifications
-)]
-
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-pub use diesel_factories_code_gen::Factory;
-
-/// A "belongs to" association that may or may not have been inserted yet.
-///
-/// You will normally be using this when setting up "belongs to" associations between models in
-/// factories.
-#[derive(Debug, Clone)]
-pub enum Association<'a, Model, Factory> {
-    /// An associated model that has been inserted into the database.
-    ///
-    /// You shouldn't have to use this direclty but instead just `Association::default()`.
-    Model(&'a Model),
-
-    /// A factory for a model that hasn't been inserted yet into the database.
-    ///
-    /// You shouldn't have to use this direclty but instead just `Association::default()`.
-    Factory(Factory),
-}
-
-impl<Model, Factory: Default> Default for Association<'_, Model, Factory> {
-    fn default() -> Self {
-        Association::Factory(Factory::default())
-    }
-}
-
-impl<'a, Model, Factory> Association<'a, Model, Factory> {
-    #[doc(hidden)]
-    pub fn new_model(inner: &'a Model) -> Self {
-        Association::Model(inner)
-    }
-
-    #[doc(hidden)]
-    pub fn new_factory(inner: Factory) -> Self {
-        Association::Factory(inner)
-    }
-}
-
-impl<M, F> Association<'_, M, F>
-where
-    F: Factory<Model = M> + Clone,
-{
-    #[doc(hidden)]
-    pub fn insert_returning_id(&self, con: &F::Connection) -> F::Id {
-        match self {
-            Association::Model(model) => F::id_for_model(&model).clone(),
-            Association::Factory(factory) => {
-                let model = factory.clone().insert(con);
-                F::id_for_model(&model).clone()
-            }
-        }
-    }
-}
-
-/// A generic factory trait.
-///
-/// You shouldn't ever have to implement this trait yourself. It can be derived using
-/// `#[derive(Factory)]`
-///
-/// See the [root module docs](/) for info on how to use `#[derive(Factory)]`.
-pub trait Factory: Clone {
-    /// The model type the factory inserts.
-    ///
-    /// For a factory named `UserFactory` this would probably be `User`.
-    type Model;
+//!
+//! Errors returned by the callbacks are returned from [`try_insert`]. Callbacks aren't supported
+//! on factories with an `async_connection`.
+//!
+//! [`try_insert`]: trait.Factory.html#tymethod.try_insert
+//!
+//! ### Field defaults and fake data
+//!
+//! Instead of implementing `Default` yourself you can set the default of each field with
+//! `#[factory(default = ...)]`. With the `fake` feature enabled `#[factory(fake = "...")]` sets
+//! the default to a value from one of the generators in the [`fake`](fake/index.html) module. If
+//! any field has one of these the derive implements `Default`, using `Default::default()` for the
+//! other fields:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct User {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub email: String,
+//! #     pub age: i32,
+//! #     pub country_id: i32,
+//! # }
+//! #
+//! # // Only compiled with the `fake` feature
+//! # #[cfg(feature = "fake")]
+//! #[derive(Clone, Factory)]
+//! #[factory(model = User, table = crate::schema::users)]
+//! struct UserFactory<'a> {
+//!     #[factory(fake = "name")]
+//!     pub name: String,
+//!     #[factory(fake = "unique_email")]
+//!     pub email: String,
+//!     #[factory(default = 30)]
+//!     pub age: i32,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//! #
+//! # #[cfg(feature = "fake")]
+//! # fn main() {
+//! let user = UserFactory::default();
+//! assert_eq!(30, user.age);
+//! # }
+//! #
+//! # #[cfg(not(feature = "fake"))]
+//! # fn main() {}
+//! ```
+//!
+//! ### Computed fields
+//!
+//! Fields whose value depends on other fields can be computed from the factory with
+//! `#[factory(computed = ...)]`. The closure runs when the factory is inserted or built, after all
+//! builder methods have been called. Computed fields must be `Option`s of the column's type,
+//! which are `None` unless the value is set with the builder method:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(transient)]
+//!     pub city_name: &'static str,
+//!     #[factory(computed = |f| f.city_name.to_uppercase())]
+//!     pub name: Option<String>,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             city_name: "", name: None, country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! # fn main() {
+//! // Inserted as "AARHUS"
+//! CityFactory::default().city_name("Aarhus");
+//!
+//! // Inserted as "Aarhus"
+//! CityFactory::default().city_name("Aarhus").name("Aarhus");
+//! # }
+//! ```
+//!
+//! ### Variants
+//!
+//! Combinations of fields you set often can be named with `variant(...)`. Each variant becomes a
+//! method on the factory calling the builder methods of the fields it sets, so variants compose
+//! with each other and with the other builder methods:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//!     variant(
+//!         capital = { name: "Copenhagen" },
+//!         swedish = { country: CountryFactory::default().name("Sweden") },
+//!     ),
+//! )]
+//! struct CityFactory<'a> {
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: String::new(), country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! # fn main() {
+//! CityFactory::default().swedish().capital().name("Stockholm");
+//! # }
+//! ```
+//!
+//! Setting a field the factory doesn't have in a variant is a compile error.
+//!
+//! ### Shared associations
+//!
+//! Each `Association` holding a factory inserts its own row, so a user and their home city both
+//! defaulting to a new country insert two countries. Use [`Association::shared`] to insert a
+//! factory once and reuse the row wherever the association, or a clone of it, is used:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup_with_city_factory.rs");
+//! #
+//! # #[derive(Clone, Factory)]
+//! # #[factory(
+//! #     model = User,
+//! #     table = crate::schema::users,
+//! # )]
+//! # struct UserFactory<'a> {
+//! #     pub name: String,
+//! #     pub age: i32,
+//! #     pub country: Option<Association<'a, Country, CountryFactory>>,
+//! #     pub home_city: Option<Association<'a, City, CityFactory<'a>>>,
+//! #     pub current_city: Option<Association<'a, City, CityFactory<'a>>>,
+//! # }
+//! #
+//! # impl<'a> Default for UserFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: "Bob".into(), age: 30, country: None, home_city: None, current_city: None,
+//! #         }
+//! #     }
+//! # }
+//! #
+//! fn user_with_home_city(con: &diesel::pg::PgConnection) -> User {
+//!     let country = Association::shared(CountryFactory::default());
+//!
+//!     // Inserts a single country
+//!     UserFactory::default()
+//!         .country(country.clone())
+//!         .home_city(Some(CityFactory::default().country(country)))
+//!         .insert(con)
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! The association isn't limited to a single insert. The inserted model lives as long as the
+//! association or any of its clones, across inserts, connections and transactions, and models
+//! built without a database use it too. Keep a shared association within a single test, and call
+//! [`Association::reset_shared`] if you need it to insert a new row, for example after rolling
+//! back the transaction it was inserted in.
+//!
+//! With async factories the row is only guaranteed to be inserted once when the inserts using the
+//! association run one after another. Clones inserted concurrently may each insert a row.
+//!
+//! [`Association::shared`]: enum.Association.html#method.shared
+//! [`Association::reset_shared`]: enum.Association.html#method.reset_shared
+//!
+//! ### Has many associations
+//!
+//! Factories can insert child records after themselves with [`HasMany`] fields. Each child is
+//! inserted with its association back to the parent set to the inserted parent:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup_with_city_factory.rs");
+//! # use diesel::pg::PgConnection;
+//! use diesel_factories::HasMany;
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = Country,
+//!     table = crate::schema::countries,
+//! )]
+//! struct CountryWithCitiesFactory {
+//!     pub name: String,
+//!     pub cities: HasMany<CityFactory<'static>>,
+//! }
+//!
+//! impl Default for CountryWithCitiesFactory {
+//!     fn default() -> Self {
+//!         Self {
+//!             name: "Denmark".into(),
+//!             cities: HasMany::count(3, CityFactory::default()),
+//!         }
+//!     }
+//! }
+//!
+//! fn create_countries(con: &PgConnection) {
+//!     // Inserts the country and then its three cities
+//!     let country = CountryWithCitiesFactory::default().insert(con);
+//!
+//!     // Returns the inserted cities along with the country
+//!     let (country, cities) = CountryWithCitiesFactory::default()
+//!         .cities(vec![CityFactory::default().name("Aarhus")])
+//!         .insert_with_cities(con);
+//!     assert_eq!(country.id, cities[0].country_id);
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! The children are connected to the parent through the association on the child factory named
+//! after the parent's model, here `country` on `CityFactory`. Use `#[factory(inverse_of = ...)]`
+//! on the `HasMany` field if it's named something else.
+//!
+//! [`HasMany`]: struct.HasMany.html
+//!
+//! ### Many to many associations
+//!
+//! Models linked through a join table are set up with [`ManyToMany`] fields. After the factory is
+//! inserted each associated model is inserted, unless it's already a model, and linked with a row
+//! in the join table given by `through`:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! # use diesel_factories::ManyToMany;
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct User {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub email: String,
+//! #     pub age: i32,
+//! #     pub country_id: i32,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Team {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Team, table = crate::schema::teams)]
+//! # struct TeamFactory {
+//! #     pub name: String,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = User, table = crate::schema::users)]
+//! struct UserFactory<'a> {
+//!     pub name: String,
+//!     #[factory(through = crate::schema::team_memberships)]
+//!     pub teams: ManyToMany<'a, Team, TeamFactory>,
+//! }
+//! #
+//! # impl<'a> Default for UserFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: "Bob".into(), teams: ManyToMany::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! # fn insert_users(red: &Team, blue: &Team, con: &PgConnection) {
+//! // A user on a new team
+//! UserFactory::default().teams(vec![TeamFactory::default()]).insert(con);
+//!
+//! // A user on two existing teams
+//! UserFactory::default().teams(vec![red, blue]).insert(con);
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! The join table columns default to the model names followed by `_id`, here `user_id` and
+//! `team_id`. Use `owner_key` and `target_key` to change them.
+//!
+//! [`ManyToMany`]: struct.ManyToMany.html
+//!
+//! ### Polymorphic associations
+//!
+//! Tables that point at one of several other tables with a type column and an id column are set
+//! up with [`Polymorphic`] fields. The models it can point at are listed in `targets`, and the
+//! builder method accepts any of them or their factories:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! # use diesel_factories::Polymorphic;
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Post {
+//! #     pub id: i32,
+//! #     pub title: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Photo {
+//! #     pub id: i32,
+//! #     pub url: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Comment {
+//! #     pub id: i32,
+//! #     pub body: String,
+//! #     pub commentable_type: String,
+//! #     pub commentable_id: i32,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Post, table = crate::schema::posts)]
+//! # struct PostFactory<'a> {
+//! #     pub title: &'a str,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Photo, table = crate::schema::photos)]
+//! # struct PhotoFactory {
+//! #     pub url: String,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = Comment, table = crate::schema::comments)]
+//! struct CommentFactory<'a> {
+//!     pub body: String,
+//!     #[factory(targets = (Post: PostFactory<'a>, Photo as "photo": PhotoFactory))]
+//!     pub commentable: Polymorphic<'a, PgConnection>,
+//! }
+//!
+//! impl<'a> Default for CommentFactory<'a> {
+//!     fn default() -> Self {
+//!         Self {
+//!             body: "Nice!".into(),
+//!             commentable: Polymorphic::new("Post", PostFactory::default()),
+//!         }
+//!     }
+//! }
+//!
+//! # fn comment_on(post: &Post, con: &PgConnection) {
+//! // `commentable_type` is "Post"
+//! CommentFactory::default().commentable(post).insert(con);
+//!
+//! // `commentable_type` is "photo"
+//! CommentFactory::default().commentable(PhotoFactory::default()).insert(con);
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! The type column is set to the name of the model unless another name is given with `as`. The
+//! columns default to the field name followed by `_type` and `_id`. All the targets must use the
+//! connection type and id type given to `Polymorphic`, which defaults to `i32`.
+//!
+//! `Polymorphic` associations aren't supported on async factories or factories using
+//! `build_with`.
+//!
+//! [`Polymorphic`]: struct.Polymorphic.html
+//!
+//! ### Self-referential associations
+//!
+//! A factory can't contain itself, so associations pointing at the same table, like the parent of
+//! a category, need the factory boxed. Boxed factories can be set with or without the `Box`:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! # use diesel_factories::HasMany;
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Category {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub parent_id: Option<i32>,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = Category, table = crate::schema::categories)]
+//! struct CategoryFactory<'a> {
+//!     pub name: String,
+//!     pub parent: Option<Association<'a, Category, Box<CategoryFactory<'a>>>>,
+//!     #[factory(inverse_of = parent)]
+//!     pub children: HasMany<CategoryFactory<'a>>,
+//! }
+//! #
+//! # impl<'a> Default for CategoryFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: "Books".into(), parent: None, children: HasMany::default(),
+//! #         }
+//! #     }
+//! # }
+//!
+//! # fn insert_categories(root: &Category, con: &PgConnection) {
+//! // A category with a parent and grandparent
+//! CategoryFactory::default()
+//!     .parent(Some(CategoryFactory::default().parent(Some(root))))
+//!     .insert(con);
+//!
+//! // A chain of five categories, returning the last one
+//! CategoryFactory::default()
+//!     .build_chain(5, |category, parent| category.parent(Some(parent)))
+//!     .insert(con);
+//!
+//! // A root category with two children that each have two children
+//! CategoryFactory::default()
+//!     .build_tree(3, 2, |category, children| category.children(children))
+//!     .insert(con);
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! See [`Factory::build_chain`] and [`Factory::build_tree`] for more details.
+//!
+//! [`Factory::build_chain`]: trait.Factory.html#method.build_chain
+//! [`Factory::build_tree`]: trait.Factory.html#method.build_tree
+//!
+//! ### Customizing foreign key names
+//!
+//! You can customize the name of the foreign key for your associations like so
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//! )]
+//! struct CityFactory<'a> {
+//!     #[factory(foreign_key_name = country_id)]
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! #   pub name: String,
+//! }
+//! #
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         unimplemented!()
+//! #     }
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! ### Generic factories
+//!
+//! Factories can have type parameters, with bounds and where clauses, which are copied to the
+//! generated implementations and builder methods. The parameters must implement `Clone` for the
+//! factory to be `Clone`:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! # use std::marker::PhantomData;
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Event {
+//! #     pub id: i32,
+//! #     pub kind: String,
+//! # }
+//! #
+//! # trait Payload {
+//! #     const KIND: &'static str;
+//! # }
+//! #
+//! # #[derive(Clone)]
+//! # struct SignUp;
+//! #
+//! # impl Payload for SignUp {
+//! #     const KIND: &'static str = "sign_up";
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = Event, table = crate::schema::events)]
+//! struct EventFactory<P>
+//! where
+//!     P: Payload + Clone,
+//! {
+//!     pub kind: String,
+//!     #[factory(transient)]
+//!     pub payload: PhantomData<P>,
+//! }
+//!
+//! impl<P: Payload + Clone> Default for EventFactory<P> {
+//!     fn default() -> Self {
+//!         Self {
+//!             kind: P::KIND.into(),
+//!             payload: PhantomData,
+//!         }
+//!     }
+//! }
+//!
+//! # fn insert_sign_up(con: &PgConnection) {
+//! EventFactory::<SignUp>::default().insert(con);
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! ### Foreign keys referencing other columns
+//!
+//! Foreign keys that reference a unique column other than the primary key, such as the code of a
+//! currency, are set up with `references`. The value is taken from that field of the associated
+//! model and the foreign key column defaults to the association name followed by the column:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Currency {
+//! #     pub id: i32,
+//! #     pub code: String,
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Order {
+//! #     pub id: i32,
+//! #     pub amount: i32,
+//! #     pub currency_code: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Currency, table = crate::schema::currencies)]
+//! # struct CurrencyFactory {
+//! #     pub code: String,
+//! #     pub name: String,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = Order, table = crate::schema::orders)]
+//! struct OrderFactory<'a> {
+//!     pub amount: i32,
+//!     // Inserted into `currency_code`
+//!     #[factory(references = code)]
+//!     pub currency: Association<'a, Currency, CurrencyFactory>,
+//! }
+//! #
+//! # impl<'a> Default for OrderFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             amount: 100, currency: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! ### Composite primary keys
+//!
+//! Tables with composite primary keys, such as join tables, are supported by setting `id_name` to
+//! a tuple of columns and `id` to a tuple of their types:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct User {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub email: String,
+//! #     pub age: i32,
+//! #     pub country_id: i32,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Team {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Membership {
+//! #     pub user_id: i32,
+//! #     pub team_id: i32,
+//! #     pub role: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = User, table = crate::schema::users)]
+//! # struct UserFactory {
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Team, table = crate::schema::teams)]
+//! # struct TeamFactory {
+//! #     pub name: String,
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = Membership,
+//!     table = crate::schema::memberships,
+//!     id = (i32, i32),
+//!     id_name = (user_id, team_id),
+//! )]
+//! struct MembershipFactory<'a> {
+//!     pub role: String,
+//!     pub user: Association<'a, User, UserFactory>,
+//!     pub team: Association<'a, Team, TeamFactory>,
+//! }
+//! #
+//! # impl<'a> Default for MembershipFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             role: "member".into(), user: Association::default(), team: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! Associations to such factories need a foreign key column for each primary key column, in the
+//! same order:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct User {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! #     pub email: String,
+//! #     pub age: i32,
+//! #     pub country_id: i32,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Team {
+//! #     pub id: i32,
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct Membership {
+//! #     pub user_id: i32,
+//! #     pub team_id: i32,
+//! #     pub role: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = User, table = crate::schema::users)]
+//! # struct UserFactory {
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Default, Factory)]
+//! # #[factory(model = Team, table = crate::schema::teams)]
+//! # struct TeamFactory {
+//! #     pub name: String,
+//! # }
+//! #
+//! # #[derive(Clone, Queryable)]
+//! # struct MembershipNote {
+//! #     pub id: i32,
+//! #     pub body: String,
+//! #     pub membership_user_id: i32,
+//! #     pub membership_team_id: i32,
+//! # }
+//! #
+//! # #[derive(Clone, Factory)]
+//! # #[factory(
+//! #     model = Membership,
+//! #     table = crate::schema::memberships,
+//! #     id = (i32, i32),
+//! #     id_name = (user_id, team_id),
+//! # )]
+//! # struct MembershipFactory<'a> {
+//! #     pub role: String,
+//! #     pub user: Association<'a, User, UserFactory>,
+//! #     pub team: Association<'a, Team, TeamFactory>,
+//! # }
+//! #
+//! # impl<'a> Default for MembershipFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             role: "member".into(), user: Association::default(), team: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! #[derive(Clone, Factory)]
+//! #[factory(model = MembershipNote, table = crate::schema::membership_notes)]
+//! struct MembershipNoteFactory<'a> {
+//!     pub body: String,
+//!     #[factory(foreign_key_name = (membership_user_id, membership_team_id))]
+//!     pub membership: Association<'a, Membership, MembershipFactory<'a>>,
+//! }
+//! #
+//! # impl<'a> Default for MembershipNoteFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             body: "Joined".into(), membership: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! On SQLite and MySQL the primary key columns must be fields on the factory, since the inserted
+//! row is looked up using their values.
+//!
+//! ### Building models without a database
+//!
+//! Tests that don't need a database can still use factories to make models. Set `build_with` to
+//! a function that makes the model and your factory will implement [`BuildFactory`]. The function
+//! receives the primary key, the factory, and the foreign key of each association in the order
+//! they're declared:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! use diesel_factories::BuildFactory;
+//!
+//! #[derive(Clone, Factory)]
+//! #[factory(
+//!     model = City,
+//!     table = crate::schema::cities,
+//!     build_with = build_city,
+//! )]
+//! struct CityFactory<'a> {
+//!     pub name: String,
+//!     pub country: Association<'a, Country, CountryFactory>,
+//! }
+//!
+//! fn build_city(id: i32, factory: CityFactory<'_>, country_id: i32) -> City {
+//!     City {
+//!         id,
+//!         name: factory.name,
+//!         country_id,
+//!     }
+//! }
+//!
+//! # impl<'a> Default for CityFactory<'a> {
+//! #     fn default() -> Self {
+//! #         Self {
+//! #             name: "Copenhagen".into(), country: Association::default(),
+//! #         }
+//! #     }
+//! # }
+//! #
+//! # impl BuildFactory for CountryFactory {
+//! #     fn build(self) -> Country {
+//! #         Country { id: 1, name: self.name }
+//! #     }
+//! # }
+//! #
+//! # fn main() {
+//! let country = Country { id: 1, name: "Denmark".into() };
+//! let city = CityFactory::default().country(&country).build();
+//! assert_eq!(1, city.country_id);
+//! # }
+//! ```
+//!
+//! Primary keys are taken from a counter specific to each factory type so built models of the
+//! same type get different ids. Associations that are factories are built as well, so the
+//! factories of associations must also implement `BuildFactory`.
+//!
+//! [`BuildFactory`]: trait.BuildFactory.html
+//!
+//! ### Handling failed inserts
+//!
+//! [`Factory::insert`] panics if the insert fails. If you want to test that some insert is
+//! rejected by the database you can use [`Factory::try_insert`] which returns a [`FactoryError`]
+//! instead:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup_with_city_factory.rs");
+//! #
+//! fn city_requires_existing_country(con: &diesel::pg::PgConnection) {
+//!     let missing_country = Country { id: -1, name: "Atlantis".into() };
+//!
+//!     let err = CityFactory::default()
+//!         .country(&missing_country)
+//!         .try_insert(con)
+//!         .err()
+//!         .expect("Insert should fail");
+//!
+//!     assert_eq!("CityFactory", err.factory_name());
+//!     assert_eq!("cities", err.table_name());
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! ### Inserting multiple records
+//!
+//! [`Factory::insert_many`] inserts a number of copies of a factory and [`Factory::insert_each`]
+//! inserts a list of factories. Both return the models in the same order as the factories. The
+//! factories of each association are inserted first, with a single `INSERT` statement per
+//! associated table, and then all the rows with a single `INSERT` statement:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! fn seed_countries(con: &PgConnection) {
+//!     let countries = CountryFactory::default().insert_many(500, con);
+//!     assert_eq!(500, countries.len());
+//!
+//!     let countries = CountryFactory::insert_each(
+//!         vec![
+//!             CountryFactory::default().name("Denmark"),
+//!             CountryFactory::default().name("Sweden"),
+//!         ],
+//!         con,
+//!     );
+//!     assert_eq!("Sweden", countries[1].name);
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! Rows are inserted one at a time, still in a single call, for factories that:
+//!
+//! - Have no fields, since several rows can't be inserted with `DEFAULT VALUES`.
+//! - Insert into SQLite or MySQL, which can't return all the inserted rows. See
+//!   [below](#sqlite-and-mysql).
+//! - Have `before_insert` or `after_insert` callbacks, which run for each model.
+//! - Have `HasMany` or `ManyToMany` fields, whose rows are inserted after each model.
+//!
+//! `Polymorphic` associations are also inserted one at a time, before the batched `INSERT` of the
+//! factories using them.
+//!
+//! Use [`Factory::insert_list`] if each record needs to be slightly different. The closure
+//! receives the index of the record along with a copy of the factory:
+//!
+//! ```
+//! # #![allow(unused_imports)]
+//! # include!("../tests/docs_setup.rs");
+//! #
+//! fn seed_countries(con: &PgConnection) {
+//!     let countries = CountryFactory::default().insert_list(
+//!         3,
+//!         |i, country| country.name(format!("Country {}", i)),
+//!         con,
+//!     );
+//!     assert_eq!("Country 2", countries[2].name);
+//! }
+//! #
+//! # fn main() {}
+//! ```
+//!
+//! [`Factory::build_list`] does the same but returns the factories without inserting them.
+//!
+//! [`Factory::insert`]: trait.Factory.html#method.insert
+//! [`Factory::try_insert`]: trait.Factory.html#tymethod.try_insert
+//! [`FactoryError`]: struct.FactoryError.html
+//! [`Factory::insert_many`]: trait.Factory.html#method.insert_many
+//! [`Factory::insert_each`]: trait.Factory.html#method.insert_each
+//! [`Factory::insert_list`]: trait.Factory.html#method.insert_list
+//! [`Factory::build_list`]: trait.Factory.html#method.build_list
+#![doc(html_root_url = "https://docs.rs/diesel-factories/2.0.0")]
+#![deny(
+    mutable_borrow_reservation_conflict,
+    missing_docs,
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+#[cfg(all(feature = "diesel1", feature = "diesel2"))]
+compile_error!("The `diesel1` and `diesel2` features cannot be enabled at the same time");
+
+#[cfg(not(any(feature = "diesel1", feature = "diesel2")))]
+compile_error!("Either the `diesel1` or the `diesel2` feature must be enabled");
+
+#[cfg(feature = "diesel1")]
+#[doc(hidden)]
+pub extern crate diesel;
+
+#[cfg(feature = "diesel2")]
+#[doc(hidden)]
+pub extern crate diesel2 as diesel;
+
+#[cfg(feature = "async")]
+#[doc(hidden)]
+pub extern crate diesel_async;
+
+use std::{
+    cell::RefCell,
+    collections::{hash_map::RandomState, HashMap},
+    convert::TryFrom,
+    env,
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    iter::FromIterator,
+    marker::PhantomData,
+    mem,
+    ops::Range,
+    panic,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Once, PoisonError,
+    },
+};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
+
+pub use diesel_factories_code_gen::Factory;
+
+/// A "belongs to" association that may or may not have been inserted yet.
+///
+/// You will normally be using this when setting up "belongs to" associations between models in
+/// factories.
+///
+/// More variants may be added in the future, so matches on it need a wildcard arm.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Association<'a, Model, Factory> {
+    /// An associated model that has been inserted into the database.
+    ///
+    /// You shouldn't have to use this direclty but instead just `Association::default()`.
+    Model(&'a Model),
+
+    /// An associated model that has been inserted into the database, owned by the association.
+    ///
+    /// Unlike [`Association::Model`](#variant.Model) this doesn't borrow the model, so factories
+    /// using it can be `'static` and don't need a lifetime.
+    Owned(Model),
+
+    /// An associated model that has been inserted into the database, shared with an `Arc`.
+    SharedModel(Arc<Model>),
+
+    /// A factory for a model that hasn't been inserted yet into the database.
+    ///
+    /// You shouldn't have to use this direclty but instead just `Association::default()`.
+    Factory(Factory),
+
+    /// A factory that is inserted once and shared by all clones of the association.
+    ///
+    /// Made with [`Association::shared`](#method.shared).
+    Shared(SharedFactory<Model, Factory>),
+}
+
+/// An [`Association`] that doesn't borrow its model, for factories without a lifetime parameter.
+///
+/// It can be set to a factory, an owned model or an `Arc` of a model, but not a reference.
+pub type OwnedAssociation<Model, Factory> = Association<'static, Model, Factory>;
+
+impl<Model, Factory: Default> Default for Association<'_, Model, Factory> {
+    fn default() -> Self {
+        Association::Factory(Factory::default())
+    }
+}
+
+impl<'a, Model, Factory> Association<'a, Model, Factory> {
+    #[doc(hidden)]
+    pub fn new_model(inner: &'a Model) -> Self {
+        Association::Model(inner)
+    }
+
+    #[doc(hidden)]
+    pub fn new_owned(inner: Model) -> Self {
+        Association::Owned(inner)
+    }
+
+    #[doc(hidden)]
+    pub fn new_shared_model(inner: Arc<Model>) -> Self {
+        Association::SharedModel(inner)
+    }
+
+    #[doc(hidden)]
+    pub fn new_factory(inner: Factory) -> Self {
+        Association::Factory(inner)
+    }
+
+    /// Make an association that inserts `factory` the first time it's needed and reuses the
+    /// inserted model after that.
+    ///
+    /// Clones of the association share the model, so using clones for several associations in the
+    /// same insert makes them all point to the same row. See the [root module
+    /// docs](index.html#shared-associations) for an example.
+    ///
+    /// The inserted model is kept until the association and all its clones are dropped, or until
+    /// [`reset_shared`](#method.reset_shared) is called, not just for a single insert. It isn't
+    /// tied to a connection or a transaction, so reusing the association after the row has been
+    /// rolled back or deleted, or with another database, will point at a row that doesn't exist.
+    ///
+    /// Factories built with [`BuildFactory::build`] share the association the same way. They use
+    /// the inserted model if there is one, and otherwise a model built once and reused by later
+    /// builds.
+    ///
+    /// [`BuildFactory::build`]: trait.BuildFactory.html#tymethod.build
+    pub fn shared(factory: Factory) -> Self {
+        Association::Shared(SharedFactory {
+            factory,
+            model: Arc::new(Mutex::new(None)),
+            built: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Forget the model inserted or built by a shared association, so the next insert using it or
+    /// any of its clones inserts a new row.
+    ///
+    /// Does nothing for associations not made with [`Association::shared`](#method.shared).
+    pub fn reset_shared(&self) {
+        if let Association::Shared(shared) = self {
+            shared.reset();
+        }
+    }
+}
+
+/// A factory shared between clones of an [`Association`], inserted at most once.
+///
+/// [`Association`]: enum.Association.html
+#[derive(Debug)]
+pub struct SharedFactory<Model, Factory> {
+    factory: Factory,
+    model: Arc<Mutex<Option<Model>>>,
+    /// The model made by `BuildFactory::build`, used until a model is inserted.
+    built: Arc<Mutex<Option<Model>>>,
+}
+
+impl<Model, Factory: Clone> Clone for SharedFactory<Model, Factory> {
+    fn clone(&self) -> Self {
+        SharedFactory {
+            factory: self.factory.clone(),
+            model: Arc::clone(&self.model),
+            built: Arc::clone(&self.built),
+        }
+    }
+}
+
+impl<Model, Factory> SharedFactory<Model, Factory> {
+    /// Get the id of the shared model, calling `insert` if it hasn't been inserted yet.
+    fn id_or_insert<Id, E>(
+        &self,
+        id_for_model: impl FnOnce(&Model) -> Id,
+        insert: impl FnOnce(&Factory) -> Result<Model, E>,
+    ) -> Result<Id, E> {
+        // The lock is held while inserting so the model is only inserted once
+        let mut model = self.model.lock().unwrap_or_else(PoisonError::into_inner);
+        if model.is_none() {
+            *model = Some(insert(&self.factory)?);
+        }
+        Ok(id_for_model(model.as_ref().unwrap()))
+    }
+
+    /// Get the id of the inserted model, or of the built one, calling `build` if neither exists.
+    fn id_or_build<Id>(
+        &self,
+        id_for_model: impl FnOnce(&Model) -> Id,
+        build: impl FnOnce(&Factory) -> Model,
+    ) -> Id {
+        let model = self.model.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(model) = model.as_ref() {
+            return id_for_model(model);
+        }
+        drop(model);
+
+        let mut built = self.built.lock().unwrap_or_else(PoisonError::into_inner);
+        id_for_model(built.get_or_insert_with(|| build(&self.factory)))
+    }
+
+    fn reset(&self) {
+        *self.model.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        *self.built.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    #[cfg(feature = "async")]
+    fn id<Id>(&self, id_for_model: impl FnOnce(&Model) -> Id) -> Option<Id> {
+        let model = self.model.lock().unwrap_or_else(PoisonError::into_inner);
+        model.as_ref().map(id_for_model)
+    }
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: Factory<Model = M> + Clone,
+{
+    #[doc(hidden)]
+    pub fn insert_returning_id(&self, con: ConnectionRef<'_, F::Connection>) -> F::Id {
+        match self {
+            Association::Model(model) => F::id_for_model(&model),
+            Association::Owned(model) => F::id_for_model(model),
+            Association::SharedModel(model) => F::id_for_model(model),
+            Association::Factory(factory) => {
+                let model = factory.clone().insert(con);
+                F::id_for_model(&model)
+            }
+            Association::Shared(shared) => match shared
+                .id_or_insert(F::id_for_model, |factory| factory.clone().try_insert(con))
+            {
+                Ok(id) => id,
+                Err(err) => panic!("Insert of factory failed: {}", err),
+            },
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn try_insert_returning_id(
+        &self,
+        con: ConnectionRef<'_, F::Connection>,
+    ) -> Result<F::Id, FactoryError> {
+        self.try_insert_returning_key(con, F::id_for_model)
+    }
+
+    /// Like `try_insert_returning_id` but returns `key` of the model, for foreign keys that don't
+    /// reference the primary key.
+    #[doc(hidden)]
+    pub fn try_insert_returning_key<K>(
+        &self,
+        con: ConnectionRef<'_, F::Connection>,
+        key: fn(&M) -> K,
+    ) -> Result<K, FactoryError> {
+        match self {
+            Association::Model(model) => Ok(key(model)),
+            Association::Owned(model) => Ok(key(model)),
+            Association::SharedModel(model) => Ok(key(model)),
+            Association::Factory(factory) => {
+                let model = factory.clone().try_insert(con)?;
+                Ok(key(&model))
+            }
+            Association::Shared(shared) => {
+                shared.id_or_insert(key, |factory| factory.clone().try_insert(con))
+            }
+        }
+    }
+
+    /// Like `try_insert_returning_id` for each of `associations`, but the factories among them
+    /// are inserted with a single `try_insert_each`.
+    #[doc(hidden)]
+    pub fn try_insert_each_returning_id<'s>(
+        associations: impl IntoIterator<Item = &'s Self>,
+        con: ConnectionRef<'_, F::Connection>,
+    ) -> Result<Vec<F::Id>, FactoryError>
+    where
+        Self: 's,
+    {
+        Self::try_insert_each_returning_key(associations, con, F::id_for_model)
+    }
+
+    /// Like `try_insert_each_returning_id` but returns `key` of the models, for foreign keys that
+    /// don't reference the primary key.
+    #[doc(hidden)]
+    pub fn try_insert_each_returning_key<'s, K>(
+        associations: impl IntoIterator<Item = &'s Self>,
+        con: ConnectionRef<'_, F::Connection>,
+        key: fn(&M) -> K,
+    ) -> Result<Vec<K>, FactoryError>
+    where
+        Self: 's,
+    {
+        let mut keys = Vec::new();
+        let mut factories = Vec::new();
+        let mut factory_indices = Vec::new();
+        for association in associations {
+            if let Association::Factory(factory) = association {
+                factory_indices.push(keys.len());
+                factories.push(factory.clone());
+                keys.push(None);
+            } else {
+                keys.push(Some(association.try_insert_returning_key(con, key)?));
+            }
+        }
+
+        let models = F::try_insert_each(factories, con)?;
+        for (index, model) in factory_indices.into_iter().zip(&models) {
+            keys[index] = Some(key(model));
+        }
+        Ok(keys.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+impl<M, F> Association<'_, M, F>
+where
+    F: BuildFactory<Model = M> + Clone,
+{
+    #[doc(hidden)]
+    pub fn build_returning_id(&self) -> F::Id {
+        self.build_returning_key(F::id_for_model)
+    }
+
+    #[doc(hidden)]
+    pub fn build_returning_key<K>(&self, key: fn(&M) -> K) -> K {
+        match self {
+            Association::Model(model) => key(model),
+            Association::Owned(model) => key(model),
+            Association::SharedModel(model) => key(model),
+            Association::Factory(factory) => {
+                let model = factory.clone().build();
+                key(&model)
+            }
+            Association::Shared(shared) => {
+                shared.id_or_build(key, |factory| factory.clone().build())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<M, F> Association<'_, M, F>
+where
+    F: AsyncFactory<Model = M>,
+    M: Send,
+{
+    #[doc(hidden)]
+    pub fn try_insert_returning_id_async<'con>(
+        &self,
+        con: &'con mut F::Connection,
+    ) -> BoxFuture<'con, Result<F::Id, FactoryError>>
+    where
+        F: 'con,
+        M: 'con,
+    {
+        self.try_insert_returning_key_async(con, F::id_for_model)
+    }
+
+    #[doc(hidden)]
+    pub fn try_insert_returning_key_async<'con, K>(
+        &self,
+        con: &'con mut F::Connection,
+        key: fn(&M) -> K,
+    ) -> BoxFuture<'con, Result<K, FactoryError>>
+    where
+        F: 'con,
+        M: 'con,
+        K: Send + 'con,
+    {
+        match self {
+            Association::Model(model) => {
+                let id = key(model);
+                Box::pin(async move { Ok(id) })
+            }
+            Association::Owned(model) => {
+                let id = key(model);
+                Box::pin(async move { Ok(id) })
+            }
+            Association::SharedModel(model) => {
+                let id = key(model);
+                Box::pin(async move { Ok(id) })
+            }
+            Association::Factory(factory) => {
+                let factory = factory.clone();
+                Box::pin(async move {
+                    let model = factory.try_insert(con).await?;
+                    Ok(key(&model))
+                })
+            }
+            Association::Shared(shared) => {
+                if let Some(id) = shared.id(key) {
+                    return Box::pin(async move { Ok(id) });
+                }
+
+                // The lock can't be held across the insert, so this isn't single-flight: clones
+                // inserted concurrently may each insert a row. The first model stored wins and
+                // the others are only referenced by the insert that made them.
+                let shared = shared.clone();
+                Box::pin(async move {
+                    let model = shared.factory.clone().try_insert(con).await?;
+                    shared.id_or_insert(key, |_| Ok(model))
+                })
+            }
+        }
+    }
+}
+
+/// A polymorphic "belongs to" association that can point at rows in one of several tables.
+///
+/// The association is stored in two columns, one with the type of the associated model and one
+/// with its id. The models it can point at are configured with `#[factory(targets = ...)]` on the
+/// field. See the [root module docs](index.html#polymorphic-associations) for more info.
+pub struct Polymorphic<'a, Connection, Id = i32> {
+    type_name: &'static str,
+    target: Box<dyn PolymorphicTarget<'a, Connection, Id> + 'a>,
+}
+
+impl<'a, Connection, Id> Polymorphic<'a, Connection, Id> {
+    /// Make an association that inserts `factory` and stores `type_name` in the type column.
+    ///
+    /// You'll normally only need this for the `Default` implementation of your factory, the
+    /// builder methods set the type for you.
+    pub fn new<F>(type_name: &'static str, factory: F) -> Self
+    where
+        F: Factory<Connection = Connection, Id = Id> + 'a,
+        F::Model: Clone + 'a,
+    {
+        Self::new_association(type_name, Association::new_factory(factory))
+    }
+
+    #[doc(hidden)]
+    pub fn new_association<M, F>(
+        type_name: &'static str,
+        association: Association<'a, M, F>,
+    ) -> Self
+    where
+        F: Factory<Model = M, Connection = Connection, Id = Id> + 'a,
+        M: Clone + 'a,
+    {
+        Polymorphic {
+            type_name,
+            target: Box::new(association),
+        }
+    }
+
+    /// The value stored in the type column.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    #[doc(hidden)]
+    pub fn try_insert_returning_id(
+        &self,
+        con: ConnectionRef<'_, Connection>,
+    ) -> Result<Id, FactoryError> {
+        self.target.try_insert_returning_id(con)
+    }
+}
+
+impl<Connection, Id> Clone for Polymorphic<'_, Connection, Id> {
+    fn clone(&self) -> Self {
+        Polymorphic {
+            type_name: self.type_name,
+            target: self.target.clone_box(),
+        }
+    }
+}
+
+impl<Connection, Id> fmt::Debug for Polymorphic<'_, Connection, Id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Polymorphic")
+            .field("type_name", &self.type_name)
+            .finish()
+    }
+}
+
+/// The association behind a [`Polymorphic`], with the model and factory types erased.
+///
+/// [`Polymorphic`]: struct.Polymorphic.html
+trait PolymorphicTarget<'a, Connection, Id> {
+    fn try_insert_returning_id(
+        &self,
+        con: ConnectionRef<'_, Connection>,
+    ) -> Result<Id, FactoryError>;
+
+    fn clone_box(&self) -> Box<dyn PolymorphicTarget<'a, Connection, Id> + 'a>;
+}
+
+impl<'a, M, F> PolymorphicTarget<'a, F::Connection, F::Id> for Association<'a, M, F>
+where
+    F: Factory<Model = M> + 'a,
+    M: Clone + 'a,
+{
+    fn try_insert_returning_id(
+        &self,
+        con: ConnectionRef<'_, F::Connection>,
+    ) -> Result<F::Id, FactoryError> {
+        Association::try_insert_returning_id(self, con)
+    }
+
+    fn clone_box(&self) -> Box<dyn PolymorphicTarget<'a, F::Connection, F::Id> + 'a> {
+        Box::new(self.clone())
+    }
+}
+
+/// A "has many" association with child records that are inserted after the parent.
+///
+/// The children are inserted with their association back to the parent set to the inserted
+/// parent. See the [root module docs](/) for more info.
+#[derive(Debug, Clone)]
+pub struct HasMany<Factory>(Vec<Factory>);
+
+impl<Factory> HasMany<Factory> {
+    /// Insert each of the given factories as children.
+    pub fn new(factories: Vec<Factory>) -> Self {
+        HasMany(factories)
+    }
+
+    /// Insert `count` copies of `factory` as children.
+    pub fn count(count: usize, factory: Factory) -> Self
+    where
+        Factory: Clone,
+    {
+        HasMany((0..count).map(|_| factory.clone()).collect())
+    }
+}
+
+impl<Factory> Default for HasMany<Factory> {
+    fn default() -> Self {
+        HasMany(Vec::new())
+    }
+}
+
+impl<Factory> From<Vec<Factory>> for HasMany<Factory> {
+    fn from(factories: Vec<Factory>) -> Self {
+        HasMany(factories)
+    }
+}
+
+impl<Factory> IntoIterator for HasMany<Factory> {
+    type Item = Factory;
+    type IntoIter = std::vec::IntoIter<Factory>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// A "many to many" association through a join table.
+///
+/// Each associated model is inserted, unless it has been inserted already, and linked to the
+/// factory's model with a row in the join table. See the [root module docs](/) for more info.
+#[derive(Debug, Clone)]
+pub struct ManyToMany<'a, Model, Factory>(Vec<Association<'a, Model, Factory>>);
+
+impl<'a, Model, Factory> ManyToMany<'a, Model, Factory> {
+    #[doc(hidden)]
+    pub fn associations(&self) -> &[Association<'a, Model, Factory>] {
+        &self.0
+    }
+}
+
+impl<Model, Factory> Default for ManyToMany<'_, Model, Factory> {
+    fn default() -> Self {
+        ManyToMany(Vec::new())
+    }
+}
+
+impl<'a, Model, Factory> From<Vec<Association<'a, Model, Factory>>>
+    for ManyToMany<'a, Model, Factory>
+{
+    fn from(associations: Vec<Association<'a, Model, Factory>>) -> Self {
+        ManyToMany(associations)
+    }
+}
+
+impl<'a, Model, Factory> FromIterator<Association<'a, Model, Factory>>
+    for ManyToMany<'a, Model, Factory>
+{
+    fn from_iter<I: IntoIterator<Item = Association<'a, Model, Factory>>>(iter: I) -> Self {
+        ManyToMany(iter.into_iter().collect())
+    }
+}
+
+/// A reference to a database connection, as taken by [`Factory::insert`].
+///
+/// This is `&Connection` with Diesel 1 and `&mut Connection` with Diesel 2, since Diesel 2 requires
+/// mutable connections for running queries.
+///
+/// [`Factory::insert`]: trait.Factory.html#method.insert
+#[cfg(feature = "diesel1")]
+pub type ConnectionRef<'a, Connection> = &'a Connection;
+
+/// A reference to a database connection, as taken by [`Factory::insert`].
+///
+/// This is `&Connection` with Diesel 1 and `&mut Connection` with Diesel 2, since Diesel 2 requires
+/// mutable connections for running queries.
+///
+/// [`Factory::insert`]: trait.Factory.html#method.insert
+#[cfg(feature = "diesel2")]
+pub type ConnectionRef<'a, Connection> = &'a mut Connection;
+
+/// A generic factory trait.
+///
+/// You shouldn't ever have to implement this trait yourself. It can be derived using
+/// `#[derive(Factory)]`
+///
+/// See the [root module docs](/) for info on how to use `#[derive(Factory)]`.
+pub trait Factory: Clone {
+    /// The model type the factory inserts.
+    ///
+    /// For a factory named `UserFactory` this would probably be `User`.
+    type Model;
+
+    /// The primary key type your model uses.
+    ///
+    /// This will normally be i32 or i64 but can be whatever you need.
+    type Id: Clone;
+
+    /// The database connection type you use such as `diesel::pg::PgConnection`.
+    type Connection;
+
+    /// Insert the factory into the database.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails. Should be fine since you want panics early in tests.
+    fn insert(self, con: ConnectionRef<'_, Self::Connection>) -> Self::Model {
+        match self.try_insert(con) {
+            Ok(model) => model,
+            Err(err) => panic!("Insert of factory failed: {}", err),
+        }
+    }
+
+    /// Insert the factory into the database, returning an error if the insert fails.
+    ///
+    /// Useful for tests that check that some insert is rejected by the database. Associations
+    /// that haven't been inserted yet are inserted first and errors from those are returned as
+    /// well.
+    fn try_insert(
+        self,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Result<Self::Model, FactoryError>;
+
+    /// Insert `count` copies of the factory into the database.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_many(
+        self,
+        count: usize,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Vec<Self::Model> {
+        Self::insert_each((0..count).map(|_| self.clone()), con)
+    }
+
+    /// Insert `count` copies of the factory into the database, returning an error if the insert
+    /// fails.
+    fn try_insert_many(
+        self,
+        count: usize,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Result<Vec<Self::Model>, FactoryError> {
+        Self::try_insert_each((0..count).map(|_| self.clone()), con)
+    }
+
+    /// Make `count` copies of the factory, each customized by `f`.
+    ///
+    /// `f` receives the index of the copy, starting at zero, along with the copy itself. The
+    /// factories aren't inserted. Use [`insert_list`] for that.
+    ///
+    /// [`insert_list`]: #method.insert_list
+    fn build_list<F>(self, count: usize, mut f: F) -> Vec<Self>
+    where
+        F: FnMut(usize, Self) -> Self,
+    {
+        (0..count).map(|i| f(i, self.clone())).collect()
+    }
+
+    /// Make a chain of `depth` copies of the factory where each copy is the parent of the next.
+    ///
+    /// `set_parent` receives a copy and the chain built so far, and must return the copy with its
+    /// parent association set to the chain. The last copy is returned, so inserting it inserts
+    /// the whole chain. A `depth` of zero or one gives the factory itself. See the [root module
+    /// docs](index.html#self-referential-associations) for an example.
+    fn build_chain<F>(self, depth: usize, mut set_parent: F) -> Self
+    where
+        F: FnMut(Self, Self) -> Self,
+    {
+        (1..depth).fold(self.clone(), |parent, _| set_parent(self.clone(), parent))
+    }
+
+    /// Make a tree of copies of the factory, `depth` levels deep where every copy above the last
+    /// level has `breadth` children.
+    ///
+    /// `set_children` receives a copy and its children, and must return the copy with the children
+    /// set, normally as a [`HasMany`] field. The root is returned, so inserting it inserts the
+    /// whole tree.
+    ///
+    /// [`HasMany`]: struct.HasMany.html
+    fn build_tree<F>(self, depth: usize, breadth: usize, mut set_children: F) -> Self
+    where
+        F: FnMut(Self, Vec<Self>) -> Self,
+    {
+        fn build<T, F>(factory: &T, depth: usize, breadth: usize, set_children: &mut F) -> T
+        where
+            T: Clone,
+            F: FnMut(T, Vec<T>) -> T,
+        {
+            if depth <= 1 {
+                return factory.clone();
+            }
+
+            let children = (0..breadth)
+                .map(|_| build(factory, depth - 1, breadth, set_children))
+                .collect();
+            set_children(factory.clone(), children)
+        }
+
+        build(&self, depth, breadth, &mut set_children)
+    }
+
+    /// Insert `count` copies of the factory, each customized by `f`.
+    ///
+    /// This is the same as calling [`build_list`] and inserting the factories with
+    /// [`insert_each`].
+    ///
+    /// [`build_list`]: #method.build_list
+    /// [`insert_each`]: #method.insert_each
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_list<F>(
+        self,
+        count: usize,
+        f: F,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Vec<Self::Model>
+    where
+        F: FnMut(usize, Self) -> Self,
+    {
+        Self::insert_each(self.build_list(count, f), con)
+    }
+
+    /// Insert each of the factories into the database.
+    ///
+    /// The models are returned in the same order as the factories. Derived factories insert all
+    /// rows with a single `INSERT` statement per table when they can. See the [root module
+    /// docs](index.html#inserting-multiple-records) for the factories that insert one row at a
+    /// time.
+    ///
+    /// # Panics
+    /// This will panic if the insert fails.
+    fn insert_each<I>(factories: I, con: ConnectionRef<'_, Self::Connection>) -> Vec<Self::Model>
+    where
+        I: IntoIterator<Item = Self>,
+    {
+        match Self::try_insert_each(factories, con) {
+            Ok(models) => models,
+            Err(err) => panic!("Insert of factory failed: {}", err),
+        }
+    }
+
+    /// Insert each of the factories into the database, returning an error if the insert fails.
+    ///
+    /// The default implementation inserts the factories one at a time.
+    fn try_insert_each<I>(
+        factories: I,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Result<Vec<Self::Model>, FactoryError>
+    where
+        I: IntoIterator<Item = Self>,
+    {
+        factories
+            .into_iter()
+            .map(|factory| factory.try_insert(con))
+            .collect()
+    }
+
+    /// Get the primary key value for a model type.
+    ///
+    /// Just a generic wrapper around `model.id`, or a tuple of the primary key columns for tables
+    /// with composite primary keys.
+    fn id_for_model(model: &Self::Model) -> Self::Id;
+}
+
+/// Boxed factories insert the factory in the box, which is what makes self-referential
+/// associations such as `Association<'a, Category, Box<CategoryFactory<'a>>>` possible.
+impl<F: Factory> Factory for Box<F> {
+    type Model = F::Model;
+    type Id = F::Id;
+    type Connection = F::Connection;
+
+    fn try_insert(
+        self,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Result<Self::Model, FactoryError> {
+        (*self).try_insert(con)
+    }
+
+    fn try_insert_each<I>(
+        factories: I,
+        con: ConnectionRef<'_, Self::Connection>,
+    ) -> Result<Vec<Self::Model>, FactoryError>
+    where
+        I: IntoIterator<Item = Self>,
+    {
+        F::try_insert_each(factories.into_iter().map(|factory| *factory), con)
+    }
+
+    fn id_for_model(model: &Self::Model) -> Self::Id {
+        F::id_for_model(model)
+    }
+}
+
+/// A factory that can build its model without inserting it into the database.
+///
+/// This trait is implemented by `#[derive(Factory)]` when the `build_with` attribute is set.
+///
+/// See the [root module docs](/) for more info.
+pub trait BuildFactory: Factory {
+    /// Build the model in memory without touching the database.
+    ///
+    /// The model gets a primary key from a counter specific to the factory type. Associations
+    /// that haven't been inserted are built as well and their ids used as foreign keys.
+    fn build(self) -> Self::Model;
+}
+
+impl<F: BuildFactory> BuildFactory for Box<F> {
+    fn build(self) -> Self::Model {
+        (*self).build()
+    }
+}
+
+/// A boxed future as returned by [`AsyncFactory::insert`].
+///
+/// [`AsyncFactory::insert`]: trait.AsyncFactory.html#method.insert
+#[cfg(feature = "async")]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A factory that inserts with an async connection from [diesel-async][].
+///
+/// This trait is implemented by `#[derive(Factory)]` when the `async_connection` attribute is
+/// set. It requires the `async` feature.
+///
+/// See the [root module docs](/) for more info.
+///
+/// [diesel-async]: https://docs.rs/diesel-async
+#[cfg(feature = "async")]
+pub trait AsyncFactory: Clone + Send {
+    /// The model type the factory inserts.
+    type Model: Send;
+
+    /// The primary key type your model uses.
+    type Id: Clone + Send;
+
+    /// The async connection type you use such as `diesel_async::AsyncPgConnection`.
+    type Connection: Send;
+
+    /// Insert the factory into the database.
+    ///
+    /// # Panics
+    /// The future will panic if the insert fails.
+    fn insert<'con>(self, con: &'con mut Self::Connection) -> BoxFuture<'con, Self::Model>
+    where
+        Self: 'con,
+    {
+        Box::pin(async move {
+            match self.try_insert(con).await {
+                Ok(model) => model,
+                Err(err) => panic!("Insert of factory failed: {}", err),
+            }
+        })
+    }
+
+    /// Insert the factory into the database, returning an error if the insert fails.
+    fn try_insert<'con>(
+        self,
+        con: &'con mut Self::Connection,
+    ) -> BoxFuture<'con, Result<Self::Model, FactoryError>>
+    where
+        Self: 'con;
+
+    /// Get the primary key value for a model type.
+    fn id_for_model(model: &Self::Model) -> Self::Id;
+}
+
+#[cfg(feature = "async")]
+impl<F: AsyncFactory> AsyncFactory for Box<F> {
+    type Model = F::Model;
+    type Id = F::Id;
+    type Connection = F::Connection;
+
+    fn try_insert<'con>(
+        self,
+        con: &'con mut Self::Connection,
+    ) -> BoxFuture<'con, Result<Self::Model, FactoryError>>
+    where
+        Self: 'con,
+    {
+        (*self).try_insert(con)
+    }
+
+    fn id_for_model(model: &Self::Model) -> Self::Id {
+        F::id_for_model(model)
+    }
+}
+
+#[doc(hidden)]
+pub fn compute<F, T>(factory: &F, f: impl FnOnce(&F) -> T) -> T {
+    f(factory)
+}
+
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct StubIds(AtomicUsize);
+
+impl StubIds {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        StubIds(AtomicUsize::new(0))
+    }
+
+    #[doc(hidden)]
+    pub fn next<Id>(&self) -> Id
+    where
+        Id: TryFrom<usize>,
+        Id::Error: fmt::Debug,
+    {
+        let id = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+        Id::try_from(id).expect("Ran out of ids for built models")
+    }
+}
 
-    /// The primary key type your model uses.
-    ///
-    /// This will normally be i32 or i64 but can be whatever you need.
-    type Id: Clone;
+/// The error returned by [`Factory::try_insert`].
+///
+/// Contains the name of the factory and table where the insert failed and, if the insert happened
+/// while inserting associations, the names of the association fields leading to it.
+///
+/// [`Factory::try_insert`]: trait.Factory.html#tymethod.try_insert
+#[derive(Debug)]
+pub struct FactoryError {
+    factory_name: &'static str,
+    table_name: &'static str,
+    association_path: Vec<&'static str>,
+    source: diesel::result::Error,
+}
 
-    /// The database connection type you use such as `diesel::pg::PgConnection`.
-    type Connection;
+impl FactoryError {
+    #[doc(hidden)]
+    pub fn new(
+        factory_name: &'static str,
+        table_name: &'static str,
+        source: diesel::result::Error,
+    ) -> Self {
+        FactoryError {
+            factory_name,
+            table_name,
+            association_path: Vec::new(),
+            source,
+        }
+    }
 
-    /// Insert the factory into the database.
-    ///
-    /// # Panics
-    /// This will panic if the insert fails. Should be fine since you want panics early in tests.
-    fn insert(self, con: &Self::Connection) -> Self::Model;
+    #[doc(hidden)]
+    pub fn within_association(mut self, association_name: &'static str) -> Self {
+        self.association_path.insert(0, association_name);
+        self
+    }
 
-    /// Get the primary key value for a model type.
+    /// The name of the factory whose insert failed, such as `"CityFactory"`.
+    pub fn factory_name(&self) -> &'static str {
+        self.factory_name
+    }
+
+    /// The name of the table the failed insert targeted, such as `"cities"`.
+    pub fn table_name(&self) -> &'static str {
+        self.table_name
+    }
+
+    /// The association fields followed from the factory `try_insert` was called on to the one
+    /// that failed.
     ///
-    /// Just a generic wrapper around `model.id`.
-    fn id_for_model(model: &Self::Model) -> &Self::Id;
+    /// If a `UserFactory` failed to insert its `home_city` because the city's `country` couldn't
+    /// be inserted this would be `["home_city", "country"]`. Empty if the outermost insert failed.
+    pub fn association_path(&self) -> &[&'static str] {
+        &self.association_path
+    }
+
+    /// The underlying Diesel error.
+    pub fn diesel_error(&self) -> &diesel::result::Error {
+        &self.source
+    }
+
+    /// Consume the error and return the underlying Diesel error.
+    pub fn into_diesel_error(self) -> diesel::result::Error {
+        self.source
+    }
+}
+
+impl fmt::Display for FactoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Insert of `{}` into `{}` failed",
+            self.factory_name, self.table_name
+        )?;
+        if !self.association_path.is_empty() {
+            write!(
+                f,
+                " (through association `{}`)",
+                self.association_path.join(".")
+            )?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl Error for FactoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
 }
 
-static SEQUENCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static SEQUENCE_COUNTER: Sequence = Sequence::new();
 
 /// Utility function for generating unique ids or strings in factories.
 /// Each time `sequence` gets called, the closure will receive a different number.
 ///
+/// All calls share one counter. Use [`Sequence`] for a counter of your own or
+/// [`sequence_named`] for numbers that start over in each test.
+///
 /// ```
 /// use diesel_factories::sequence;
 ///
@@ -527,21 +2671,758 @@ static SEQUENCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
 ///     sequence(|i| format!("unique-string-{}", i)),
 /// );
 /// ```
+///
+/// [`Sequence`]: struct.Sequence.html
+/// [`sequence_named`]: fn.sequence_named.html
 pub fn sequence<T, F>(f: F) -> T
 where
     F: Fn(usize) -> T,
 {
-    SEQUENCE_COUNTER.fetch_add(1, Ordering::SeqCst);
-    let count = SEQUENCE_COUNTER.load(Ordering::Relaxed);
+    SEQUENCE_COUNTER.next(f)
+}
+
+/// A counter for generating unique values, such as one per factory.
+///
+/// The numbers start at 1 and are unique across threads.
+///
+/// ```
+/// use diesel_factories::Sequence;
+///
+/// static EMAILS: Sequence = Sequence::new();
+///
+/// assert_eq!("user-1@example.com", EMAILS.next(|i| format!("user-{}@example.com", i)));
+/// assert_eq!("user-2@example.com", EMAILS.next(|i| format!("user-{}@example.com", i)));
+///
+/// EMAILS.reset();
+/// assert_eq!("user-1@example.com", EMAILS.next(|i| format!("user-{}@example.com", i)));
+/// ```
+#[derive(Debug, Default)]
+pub struct Sequence(AtomicUsize);
+
+impl Sequence {
+    /// Make a sequence starting at 1.
+    pub const fn new() -> Self {
+        Sequence(AtomicUsize::new(0))
+    }
+
+    /// Call `f` with the next number in the sequence.
+    pub fn next<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(usize) -> T,
+    {
+        f(self.0.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    /// Start the sequence over at 1.
+    ///
+    /// Values made before the reset will be made again, so only do this when they're no longer
+    /// in the database.
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::SeqCst);
+    }
+}
+
+thread_local! {
+    static NAMED_SEQUENCES: RefCell<HashMap<String, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Like [`sequence`] but with a separate counter for each name.
+///
+/// The counters are kept per thread. Since each test runs on its own thread the numbers in a
+/// test don't depend on the other tests running in parallel, which keeps them stable for
+/// snapshots. Values from different tests can be the same, which is fine as long as each test
+/// inserts in its own transaction.
+///
+/// **The counters are never reset on their own.** Tests that share a thread, such as tests run
+/// with `--test-threads=1` or on a reused thread of an async runtime, continue each other's
+/// counters. Make a [`SequenceScope`] at the start of such tests, or call [`reset_sequences`],
+/// to start the counters over.
+///
+/// ```
+/// use diesel_factories::{reset_sequences, sequence_named};
+///
+/// assert_eq!("bob-1", sequence_named("name", |i| format!("bob-{}", i)));
+/// assert_eq!("bob-2", sequence_named("name", |i| format!("bob-{}", i)));
+/// assert_eq!(1, sequence_named("age", |i| i));
+///
+/// reset_sequences();
+/// assert_eq!("bob-1", sequence_named("name", |i| format!("bob-{}", i)));
+/// ```
+///
+/// [`sequence`]: fn.sequence.html
+/// [`SequenceScope`]: struct.SequenceScope.html
+/// [`reset_sequences`]: fn.reset_sequences.html
+pub fn sequence_named<T, F>(name: &str, f: F) -> T
+where
+    F: FnOnce(usize) -> T,
+{
+    let count = NAMED_SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let count = match sequences.get_mut(name) {
+            Some(count) => count,
+            None => sequences.entry(name.to_string()).or_insert(0),
+        };
+        *count += 1;
+        *count
+    });
     f(count)
 }
 
+/// Start all of the current thread's [`sequence_named`] counters over at 1.
+///
+/// [`sequence_named`]: fn.sequence_named.html
+pub fn reset_sequences() {
+    NAMED_SEQUENCES.with(|sequences| sequences.borrow_mut().clear());
+}
+
+/// Start the current thread's [`sequence_named`] counters over at 1 until the scope is dropped.
+///
+/// The counters from before the scope are restored when it's dropped, so scopes can be nested.
+///
+/// ```
+/// use diesel_factories::{sequence_named, SequenceScope};
+///
+/// assert_eq!(1, sequence_named("id", |i| i));
+///
+/// {
+///     let _scope = SequenceScope::new();
+///     assert_eq!(1, sequence_named("id", |i| i));
+///     assert_eq!(2, sequence_named("id", |i| i));
+/// }
+///
+/// assert_eq!(2, sequence_named("id", |i| i));
+/// ```
+///
+/// [`sequence_named`]: fn.sequence_named.html
+#[derive(Debug)]
+#[must_use = "the counters are restored as soon as the scope is dropped"]
+pub struct SequenceScope {
+    outer: HashMap<String, usize>,
+    // The counters are per thread, so the scope has to be dropped on the thread that made it
+    not_send: PhantomData<*const ()>,
+}
+
+impl SequenceScope {
+    /// Start the current thread's named counters over at 1.
+    pub fn new() -> Self {
+        let outer = NAMED_SEQUENCES.with(|sequences| mem::take(&mut *sequences.borrow_mut()));
+        SequenceScope {
+            outer,
+            not_send: PhantomData,
+        }
+    }
+}
+
+impl Default for SequenceScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SequenceScope {
+    fn drop(&mut self) {
+        let outer = mem::take(&mut self.outer);
+        // Ignore errors from threads that are shutting down
+        let _ = NAMED_SEQUENCES.try_with(|sequences| *sequences.borrow_mut() = outer);
+    }
+}
+
+/// The environment variable used to seed [`FactoryRng`], such as `DIESEL_FACTORIES_SEED=42`.
+///
+/// [`FactoryRng`]: struct.FactoryRng.html
+pub const SEED_ENV_VAR: &str = "DIESEL_FACTORIES_SEED";
+
+/// A small random number generator for making random but reproducible test data.
+///
+/// Each thread has its own generator, available through [`with_rng`]. It's seeded from
+/// [`SEED_ENV_VAR`] if set and with a random seed otherwise. The seed is printed when a thread
+/// that has used the generator panics, so the output of a failing test shows how to run it again
+/// with the same data:
+///
+/// ```text
+/// diesel_factories: random data seeded with DIESEL_FACTORIES_SEED=7423305826049123316
+/// ```
+///
+/// The numbers aren't suitable for anything but tests.
+///
+/// [`with_rng`]: fn.with_rng.html
+/// [`SEED_ENV_VAR`]: constant.SEED_ENV_VAR.html
+// Copying a generator by accident would repeat its numbers
+#[allow(missing_copy_implementations)]
+#[derive(Debug, Clone)]
+pub struct FactoryRng {
+    seed: u64,
+    state: u64,
+}
+
+impl FactoryRng {
+    /// Make a generator that always produces the same numbers for the same seed.
+    pub fn from_seed(seed: u64) -> Self {
+        FactoryRng { seed, state: seed }
+    }
+
+    /// The seed the generator was made with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Generate a random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        // SplitMix64
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Generate a number in `range`.
+    ///
+    /// # Panics
+    /// This will panic if the range is empty.
+    pub fn gen_range<T: RangeInteger>(&mut self, range: Range<T>) -> T {
+        let start = range.start.to_ordered_u128();
+        let end = range.end.to_ordered_u128();
+        assert!(start < end, "Can't generate a number in an empty range");
+        let len = end - start;
+        let random = if len > u128::from(u64::MAX) {
+            u128::from(self.next_u64()) << 64 | u128::from(self.next_u64())
+        } else {
+            u128::from(self.next_u64())
+        };
+        T::from_ordered_u128(start + random % len)
+    }
+
+    /// Generate `true` or `false` with equal probability.
+    pub fn gen_bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    /// Pick a random element of `items`.
+    ///
+    /// # Panics
+    /// This will panic if `items` is empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        assert!(!items.is_empty(), "Can't choose from an empty slice");
+        &items[self.gen_range(0..items.len())]
+    }
+
+    /// Generate a string of `len` random lowercase letters and digits.
+    pub fn alphanumeric(&mut self, len: usize) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+        (0..len).map(|_| char::from(*self.choose(CHARS))).collect()
+    }
+
+    /// Make a new generator seeded from this one, keeping the seed it was made with.
+    fn split(&mut self) -> FactoryRng {
+        FactoryRng {
+            seed: self.seed,
+            state: self.next_u64(),
+        }
+    }
+}
+
+/// The primitive integer types [`FactoryRng::gen_range`] can generate.
+///
+/// [`FactoryRng::gen_range`]: struct.FactoryRng.html#method.gen_range
+pub trait RangeInteger: Copy + private::Sealed {
+    /// Map the integer to a `u128` so that the order of integers is preserved.
+    #[doc(hidden)]
+    fn to_ordered_u128(self) -> u128;
+
+    /// The inverse of `to_ordered_u128`.
+    #[doc(hidden)]
+    fn from_ordered_u128(n: u128) -> Self;
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+macro_rules! impl_range_integer {
+    (unsigned: $($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+
+            #[allow(trivial_numeric_casts)]
+            impl RangeInteger for $ty {
+                fn to_ordered_u128(self) -> u128 {
+                    self as u128
+                }
+
+                fn from_ordered_u128(n: u128) -> Self {
+                    n as $ty
+                }
+            }
+        )*
+    };
+    (signed: $($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+
+            // Flipping the sign bit puts negative numbers before positive ones
+            #[allow(trivial_numeric_casts)]
+            impl RangeInteger for $ty {
+                fn to_ordered_u128(self) -> u128 {
+                    (self as i128 as u128) ^ (1 << 127)
+                }
+
+                fn from_ordered_u128(n: u128) -> Self {
+                    (n ^ (1 << 127)) as i128 as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_range_integer!(unsigned: u8, u16, u32, u64, u128, usize);
+impl_range_integer!(signed: i8, i16, i32, i64, i128, isize);
+
+thread_local! {
+    static RNG: RefCell<Option<FactoryRng>> = const { RefCell::new(None) };
+}
+
+static PRINT_SEED_ON_PANIC: Once = Once::new();
+
+/// Add a panic hook printing the seed of the panicking thread's generator, before calling the
+/// hook that was set before.
+fn print_seed_on_panic() {
+    PRINT_SEED_ON_PANIC.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let seed = RNG
+                .try_with(|rng| rng.try_borrow().ok()?.as_ref().map(FactoryRng::seed))
+                .ok()
+                .flatten();
+            if let Some(seed) = seed {
+                eprintln!(
+                    "diesel_factories: random data seeded with {}={}",
+                    SEED_ENV_VAR, seed
+                );
+            }
+            previous_hook(info);
+        }));
+    });
+}
+
+/// Call `f` with the current thread's [`FactoryRng`].
+///
+/// Useful for random defaults in factories:
+///
+/// ```
+/// use diesel_factories::with_rng;
+///
+/// struct UserFactory {
+///     name: String,
+///     age: i32,
+/// }
+///
+/// impl Default for UserFactory {
+///     fn default() -> Self {
+///         with_rng(|rng| Self {
+///             name: format!("user-{}", rng.alphanumeric(8)),
+///             age: rng.gen_range(18..80),
+///         })
+///     }
+/// }
+/// #
+/// # assert!(UserFactory::default().age >= 18);
+/// ```
+///
+/// Calls can be nested, so `f` can use the [`fake`](fake/index.html) generators or other helpers
+/// that call `with_rng`. Each call gets its own generator split off the thread's generator, which
+/// keeps the numbers reproducible as long as the calls happen in the same order.
+///
+/// # Panics
+/// This will panic if [`SEED_ENV_VAR`] isn't a number.
+///
+/// [`FactoryRng`]: struct.FactoryRng.html
+/// [`SEED_ENV_VAR`]: constant.SEED_ENV_VAR.html
+pub fn with_rng<T, F>(f: F) -> T
+where
+    F: FnOnce(&mut FactoryRng) -> T,
+{
+    print_seed_on_panic();
+
+    // The thread's generator isn't borrowed while `f` runs, so `f` can call `with_rng` again
+    let mut rng = RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        let rng = rng.get_or_insert_with(|| {
+            let seed = match env::var(SEED_ENV_VAR) {
+                Ok(seed) => seed.parse().unwrap_or_else(|_| {
+                    panic!("{} must be a number, got {:?}", SEED_ENV_VAR, seed)
+                }),
+                Err(_) => RandomState::new().build_hasher().finish(),
+            };
+            FactoryRng::from_seed(seed)
+        });
+        rng.split()
+    });
+    f(&mut rng)
+}
+
+/// Replace the current thread's [`FactoryRng`] with one seeded with `seed`.
+///
+/// [`FactoryRng`]: struct.FactoryRng.html
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Some(FactoryRng::from_seed(seed)));
+}
+
+/// Generators for realistic looking test data.
+///
+/// Requires the `fake` feature. The values are picked with the current thread's [`FactoryRng`]
+/// so they're reproducible with the same seed. Combine them with [`sequence`] when the values
+/// must be unique, or use the `unique_` generators that already do:
+///
+/// ```
+/// use diesel_factories::{fake, sequence};
+///
+/// let username = sequence(|i| format!("{}{}", fake::username(), i));
+/// let email = fake::unique_email();
+/// #
+/// # assert!(email.ends_with("@example.com"));
+/// ```
+///
+/// The generators can also be used as defaults with `#[factory(fake = "email")]`.
+///
+/// [`FactoryRng`]: ../struct.FactoryRng.html
+/// [`sequence`]: ../fn.sequence.html
+#[cfg(feature = "fake")]
+pub mod fake {
+    use super::{sequence, with_rng};
+
+    const FIRST_NAMES: &[&str] = &[
+        "Ada", "Alan", "Barbara", "Bob", "Carol", "Dennis", "Edsger", "Frances", "Grace", "Guido",
+        "Hedy", "Ivan", "Jean", "Ken", "Linus", "Margaret", "Niklaus", "Radia", "Sophie", "Tim",
+    ];
+
+    const LAST_NAMES: &[&str] = &[
+        "Allen",
+        "Berners-Lee",
+        "Dijkstra",
+        "Hopper",
+        "Kernighan",
+        "Knuth",
+        "Lamarr",
+        "Liskov",
+        "Lovelace",
+        "Perlman",
+        "Ritchie",
+        "Shaw",
+        "Sutherland",
+        "Thompson",
+        "Torvalds",
+        "Turing",
+        "van Rossum",
+        "Wilson",
+        "Wirth",
+    ];
+
+    const STREETS: &[&str] = &[
+        "Main Street",
+        "High Street",
+        "Church Road",
+        "Mill Lane",
+        "Park Avenue",
+        "Station Road",
+        "Victoria Road",
+        "Oak Street",
+        "Elm Street",
+        "Harbour Road",
+    ];
+
+    const CITIES: &[&str] = &[
+        "Aarhus",
+        "Amsterdam",
+        "Berlin",
+        "Copenhagen",
+        "Dublin",
+        "Helsinki",
+        "Lisbon",
+        "London",
+        "Madrid",
+        "Oslo",
+        "Paris",
+        "Prague",
+        "Stockholm",
+        "Vienna",
+    ];
+
+    const COUNTRIES: &[&str] = &[
+        "Austria",
+        "Czechia",
+        "Denmark",
+        "Finland",
+        "France",
+        "Germany",
+        "Ireland",
+        "Netherlands",
+        "Norway",
+        "Portugal",
+        "Spain",
+        "Sweden",
+        "United Kingdom",
+    ];
+
+    const COMPANY_WORDS: &[&str] = &[
+        "Acme", "Apex", "Blue", "Bright", "Global", "Green", "Nordic", "North", "Pioneer",
+        "Quantum", "Rapid", "Summit", "United",
+    ];
+
+    const COMPANY_SUFFIXES: &[&str] = &["Inc", "LLC", "Ltd", "Group", "Labs", "Systems"];
+
+    const LOREM: &[&str] = &[
+        "lorem",
+        "ipsum",
+        "dolor",
+        "sit",
+        "amet",
+        "consectetur",
+        "adipiscing",
+        "elit",
+        "sed",
+        "do",
+        "eiusmod",
+        "tempor",
+        "incididunt",
+        "ut",
+        "labore",
+        "et",
+        "dolore",
+        "magna",
+        "aliqua",
+        "enim",
+        "ad",
+        "minim",
+        "veniam",
+        "quis",
+        "nostrud",
+        "exercitation",
+        "ullamco",
+        "laboris",
+    ];
+
+    fn pick(items: &[&str]) -> String {
+        with_rng(|rng| rng.choose(items).to_string())
+    }
+
+    fn email_local_part(first_name: &str, last_name: &str) -> String {
+        format!("{}.{}", first_name, last_name)
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric() && c != '.', "")
+    }
+
+    /// A first name such as `"Grace"`.
+    pub fn first_name() -> String {
+        pick(FIRST_NAMES)
+    }
+
+    /// A last name such as `"Hopper"`.
+    pub fn last_name() -> String {
+        pick(LAST_NAMES)
+    }
+
+    /// A full name such as `"Grace Hopper"`.
+    pub fn name() -> String {
+        format!("{} {}", first_name(), last_name())
+    }
+
+    /// A username such as `"grace_hopper"`.
+    pub fn username() -> String {
+        email_local_part(&first_name(), &last_name()).replace('.', "_")
+    }
+
+    /// An email address such as `"grace.hopper@example.com"`.
+    pub fn email() -> String {
+        format!(
+            "{}@example.com",
+            email_local_part(&first_name(), &last_name())
+        )
+    }
+
+    /// An email address made unique with [`sequence`], such as `"grace.hopper42@example.com"`.
+    ///
+    /// [`sequence`]: ../fn.sequence.html
+    pub fn unique_email() -> String {
+        let local_part = email_local_part(&first_name(), &last_name());
+        sequence(|i| format!("{}{}@example.com", local_part, i))
+    }
+
+    /// A username made unique with [`sequence`], such as `"grace_hopper42"`.
+    ///
+    /// [`sequence`]: ../fn.sequence.html
+    pub fn unique_username() -> String {
+        let username = username();
+        sequence(|i| format!("{}{}", username, i))
+    }
+
+    /// A street address such as `"12 Mill Lane"`.
+    pub fn street_address() -> String {
+        let number = with_rng(|rng| rng.gen_range(1u32..200));
+        format!("{} {}", number, pick(STREETS))
+    }
+
+    /// A city such as `"Copenhagen"`.
+    pub fn city() -> String {
+        pick(CITIES)
+    }
+
+    /// A country such as `"Denmark"`.
+    pub fn country() -> String {
+        pick(COUNTRIES)
+    }
+
+    /// A company name such as `"Nordic Labs"`.
+    pub fn company_name() -> String {
+        format!("{} {}", pick(COMPANY_WORDS), pick(COMPANY_SUFFIXES))
+    }
+
+    /// A phone number such as `"+45 5550 1234"`.
+    pub fn phone_number() -> String {
+        with_rng(|rng| {
+            format!(
+                "+{} 555{} {}",
+                rng.gen_range(10u32..100),
+                rng.gen_range(0u32..10),
+                rng.gen_range(1000u32..10000)
+            )
+        })
+    }
+
+    /// A single lorem ipsum word.
+    pub fn word() -> String {
+        pick(LOREM)
+    }
+
+    /// A lorem ipsum sentence of 4 to 12 words.
+    pub fn sentence() -> String {
+        let len = with_rng(|rng| rng.gen_range(4u32..13));
+        let words = (0..len).map(|_| word()).collect::<Vec<_>>().join(" ");
+        let mut chars = words.chars();
+        match chars.next() {
+            Some(first) => format!("{}{}.", first.to_uppercase(), chars.as_str()),
+            None => String::new(),
+        }
+    }
+
+    /// A lorem ipsum paragraph of 3 to 6 sentences.
+    pub fn paragraph() -> String {
+        let len = with_rng(|rng| rng.gen_range(3u32..7));
+        (0..len).map(|_| sentence()).collect::<Vec<_>>().join(" ")
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[allow(unused_imports)]
     use super::*;
 
     #[test]
+    fn sequence_is_unique_across_threads() {
+        static COUNTER: Sequence = Sequence::new();
+
+        let threads = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| (0..100).map(|_| COUNTER.next(|i| i)).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        let mut numbers = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        numbers.sort_unstable();
+
+        assert_eq!((1..=400).collect::<Vec<_>>(), numbers);
+    }
+
+    #[test]
+    fn named_sequences_start_over_on_each_thread() {
+        assert_eq!(1, sequence_named("test", |i| i));
+        assert_eq!(2, sequence_named("test", |i| i));
+
+        let on_other_thread = std::thread::spawn(|| sequence_named("test", |i| i))
+            .join()
+            .unwrap();
+        assert_eq!(1, on_other_thread);
+    }
+
+    #[test]
+    fn sequence_scope_restores_outer_counters() {
+        assert_eq!(1, sequence_named("scoped", |i| i));
+
+        let scope = SequenceScope::new();
+        assert_eq!(1, sequence_named("scoped", |i| i));
+        {
+            let _inner = SequenceScope::new();
+            assert_eq!(1, sequence_named("scoped", |i| i));
+        }
+        assert_eq!(2, sequence_named("scoped", |i| i));
+        drop(scope);
+
+        assert_eq!(2, sequence_named("scoped", |i| i));
+    }
+
+    #[test]
+    fn rng_is_reproducible() {
+        let mut first = FactoryRng::from_seed(42);
+        let mut second = FactoryRng::from_seed(42);
+
+        assert_eq!(first.alphanumeric(16), second.alphanumeric(16));
+        assert_eq!(first.next_u64(), second.next_u64());
+        assert_eq!(42, first.seed());
+    }
+
+    #[test]
+    fn rng_generates_numbers_in_range() {
+        let mut rng = FactoryRng::from_seed(1);
+
+        for _ in 0..1000 {
+            let n = rng.gen_range(-3i32..3);
+            assert!((-3..3).contains(&n));
+        }
+        assert_eq!(1, rng.gen_range(1u8..2));
+    }
+
+    #[test]
+    fn rng_generates_numbers_in_wide_ranges() {
+        let mut rng = FactoryRng::from_seed(1);
+
+        for _ in 0..1000 {
+            let n = rng.gen_range(i128::MIN..i128::MAX);
+            assert!(n < i128::MAX);
+            let n = rng.gen_range(0usize..usize::MAX);
+            assert!(n < usize::MAX);
+            let n = rng.gen_range(-3isize..3);
+            assert!((-3..3).contains(&n));
+        }
+        assert_eq!(u128::MAX - 1, rng.gen_range(u128::MAX - 1..u128::MAX));
+        assert_eq!(i64::MIN, rng.gen_range(i64::MIN..i64::MIN + 1));
+    }
+
+    #[test]
+    fn seed_rng_resets_thread_rng() {
+        seed_rng(7);
+        let first = with_rng(|rng| rng.next_u64());
+        seed_rng(7);
+        let second = with_rng(|rng| rng.next_u64());
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn with_rng_can_be_nested() {
+        let nested = || with_rng(|rng| (rng.next_u64(), with_rng(|inner| inner.next_u64())));
+
+        seed_rng(7);
+        let (outer, inner) = nested();
+        seed_rng(7);
+
+        assert_ne!(outer, inner);
+        assert_eq!((outer, inner), nested());
+    }
+
+    #[test]
+    #[cfg(feature = "diesel1")]
     fn test_compile_pass() {
         let t = trybuild::TestCases::new();
         t.pass("tests/compile_pass/*.rs");
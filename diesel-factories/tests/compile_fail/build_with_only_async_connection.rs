@@ -0,0 +1,42 @@
+#![allow(proc_macro_derive_resolution_fallback, unused_imports)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::Factory;
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    async_connection = diesel_async::AsyncPgConnection,
+    build_with = build_country,
+)]
+struct CountryFactory {
+    pub name: String,
+}
+
+fn build_country(id: i32, factory: CountryFactory) -> Country {
+    Country {
+        id,
+        name: factory.name,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,71 @@
+#![allow(proc_macro_derive_resolution_fallback, unused_imports)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{Association, Factory};
+
+mod schema {
+    table! {
+        users (id) {
+            id -> Integer,
+            age -> Integer,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        countries (id) {
+            id -> Integer,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub age: i32,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = User,
+    table = crate::schema::users,
+    connection = diesel::pg::PgConnection,
+    variant(old = { age: 90, height: 180 }),
+)]
+struct UserFactory<'a> {
+    pub age: i32,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+impl Default for UserFactory<'_> {
+    fn default() -> Self {
+        Self {
+            age: 30,
+            country: Default::default(),
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+)]
+struct CountryFactory {}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {}
+    }
+}
+
+fn main() {}
@@ -0,0 +1,70 @@
+#![allow(proc_macro_derive_resolution_fallback, unused_imports)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{Association, Factory};
+
+mod schema {
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+            age -> Integer,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = User,
+    table = crate::schema::users,
+    connection = diesel::pg::PgConnection,
+)]
+struct UserFactory<'a> {
+    #[factory(default = "Bob".into())]
+    pub name: String,
+    pub age: i32,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+)]
+struct CountryFactory {
+    #[factory(default = String::from("Denmark"))]
+    pub name: String,
+}
+
+fn main() {
+    let user_factory = UserFactory::default();
+
+    assert_eq!(user_factory.name, "Bob");
+    assert_eq!(user_factory.age, 0);
+    assert_eq!(CountryFactory::default().name, "Denmark");
+}
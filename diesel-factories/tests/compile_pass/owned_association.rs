@@ -0,0 +1,78 @@
+#![allow(proc_macro_derive_resolution_fallback, unused_imports)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{Association, Factory, OwnedAssociation};
+use std::sync::Arc;
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+}
+
+#[derive(Clone, Queryable)]
+pub struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = Country, table = schema::countries)]
+struct CountryFactory {
+    pub name: String,
+}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
+
+#[derive(Clone, Queryable)]
+pub struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = City, table = schema::cities)]
+struct CityFactory {
+    pub name: String,
+    pub country: OwnedAssociation<Country, CountryFactory>,
+}
+
+impl Default for CityFactory {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            country: Association::default(),
+        }
+    }
+}
+
+fn city_in(country: Country) -> CityFactory {
+    CityFactory::default().country(country)
+}
+
+fn city_in_shared(country: Arc<Country>) -> CityFactory {
+    CityFactory::default().country(country)
+}
+
+fn main() {}
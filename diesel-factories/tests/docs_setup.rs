@@ -19,8 +19,105 @@ mod schema {
             country_id -> Integer,
         }
     }
+
+    table! {
+        posts (id) {
+            id -> Integer,
+            title -> Text,
+        }
+    }
+
+    table! {
+        photos (id) {
+            id -> Integer,
+            url -> Text,
+        }
+    }
+
+    table! {
+        comments (id) {
+            id -> Integer,
+            body -> Text,
+            commentable_type -> Text,
+            commentable_id -> Integer,
+        }
+    }
+
+    table! {
+        categories (id) {
+            id -> Integer,
+            name -> Text,
+            parent_id -> Nullable<Integer>,
+        }
+    }
+
+    table! {
+        events (id) {
+            id -> Integer,
+            kind -> Text,
+        }
+    }
+
+    table! {
+        currencies (id) {
+            id -> Integer,
+            code -> Text,
+            name -> Text,
+        }
+    }
+
+    table! {
+        orders (id) {
+            id -> Integer,
+            amount -> Integer,
+            currency_code -> Text,
+        }
+    }
+
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+            email -> Text,
+            age -> Integer,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        teams (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        team_memberships (user_id, team_id) {
+            user_id -> Integer,
+            team_id -> Integer,
+        }
+    }
+
+    table! {
+        memberships (user_id, team_id) {
+            user_id -> Integer,
+            team_id -> Integer,
+            role -> Text,
+        }
+    }
+
+    table! {
+        membership_notes (id) {
+            id -> Integer,
+            body -> Text,
+            membership_user_id -> Integer,
+            membership_team_id -> Integer,
+        }
+    }
 }
 
+// Not every doc example uses every model
+#[allow(dead_code)]
 #[derive(Clone, Queryable)]
 struct City {
     pub id: i32,
@@ -28,6 +125,7 @@ struct City {
     pub country_id: i32,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Queryable)]
 struct Country {
     pub id: i32,
@@ -31,6 +31,8 @@ mod schema {
     }
 }
 
+// Not every doc example uses every model
+#[allow(dead_code)]
 #[derive(Queryable, Clone)]
 struct User {
     pub id: i32,
@@ -41,6 +43,7 @@ struct User {
     pub current_city_id: Option<i32>,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Queryable)]
 struct City {
     pub id: i32,
@@ -48,6 +51,7 @@ struct City {
     pub country_id: i32,
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Queryable)]
 struct Country {
     pub id: i32,
@@ -4,8 +4,10 @@
 extern crate diesel;
 
 use diesel::{pg::PgConnection, prelude::*};
-use diesel_factories::{Association, Factory};
-use std::env;
+use diesel_factories::{
+    Association, BuildFactory, Factory, HasMany, ManyToMany, OwnedAssociation, Polymorphic,
+};
+use std::{env, marker::PhantomData, sync::Arc};
 
 mod schema {
     table! {
@@ -35,6 +37,54 @@ mod schema {
             country_id -> Integer,
         }
     }
+
+    table! {
+        teams (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        team_memberships (user_id, team_id) {
+            user_id -> Integer,
+            team_id -> Integer,
+        }
+    }
+
+    table! {
+        memberships (user_id, country_id) {
+            user_id -> Integer,
+            country_id -> Integer,
+            role -> Text,
+        }
+    }
+
+    table! {
+        membership_notes (id) {
+            id -> Integer,
+            body -> Text,
+            membership_user_id -> Integer,
+            membership_country_id -> Integer,
+        }
+    }
+
+    table! {
+        comments (id) {
+            id -> Integer,
+            body -> Text,
+            commentable_type -> Text,
+            commentable_id -> Integer,
+        }
+    }
+
+    table! {
+        categories (id) {
+            id -> Integer,
+            name -> Text,
+            parent_id -> Nullable<Integer>,
+        }
+    }
 }
 
 #[derive(Queryable, Clone)]
@@ -62,6 +112,42 @@ struct City {
     pub country_id: i32,
 }
 
+#[derive(Queryable, Clone)]
+struct Team {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Membership {
+    pub user_id: i32,
+    pub country_id: i32,
+    pub role: String,
+}
+
+#[derive(Queryable, Clone)]
+struct MembershipNote {
+    pub id: i32,
+    pub body: String,
+    pub membership_user_id: i32,
+    pub membership_country_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Comment {
+    pub id: i32,
+    pub body: String,
+    pub commentable_type: String,
+    pub commentable_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Category {
+    pub id: i32,
+    pub name: String,
+    pub parent_id: Option<i32>,
+}
+
 #[derive(Clone, Factory)]
 #[factory(
     model = User,
@@ -88,6 +174,62 @@ impl<'a> Default for UserFactory<'a> {
     }
 }
 
+#[derive(Clone, Factory)]
+#[factory(
+    model = User,
+    table = crate::schema::users,
+    variant(
+        teenager = { age: 16 },
+        american = { name: "Sam", country: Some(CountryFactory::default().name("USA")) },
+    )
+)]
+struct UserWithVariantsFactory<'a> {
+    pub name: &'a str,
+    pub age: i32,
+    pub country: Option<Association<'a, Country, CountryFactory>>,
+}
+
+impl<'a> Default for UserWithVariantsFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Bob",
+            age: 30,
+            country: None,
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = User, table = crate::schema::users)]
+struct UserWithTeamsFactory<'a> {
+    pub name: &'a str,
+    pub age: i32,
+    #[factory(through = crate::schema::team_memberships)]
+    pub teams: ManyToMany<'a, Team, TeamFactory>,
+}
+
+impl<'a> Default for UserWithTeamsFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Bob",
+            age: 30,
+            teams: ManyToMany::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = Team, table = crate::schema::teams)]
+struct TeamFactory {
+    pub name: String,
+}
+
+impl Default for TeamFactory {
+    fn default() -> Self {
+        Self { name: "Red".into() }
+    }
+}
+
 #[derive(Clone, Factory)]
 #[factory(
     model = Country,
@@ -106,6 +248,90 @@ impl Default for CountryFactory {
     }
 }
 
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    id_name = identity,
+    before_insert = trim_country_name,
+    after_insert = insert_capital
+)]
+struct CallbackCountryFactory {
+    pub name: String,
+    #[factory(transient)]
+    pub locale: &'static str,
+    #[factory(transient)]
+    pub capital: Option<&'static str>,
+}
+
+impl Default for CallbackCountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+            locale: "da",
+            capital: None,
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = City, table = crate::schema::cities)]
+struct CallbackCityFactory<'a> {
+    pub name: String,
+    pub team_association: String,
+    pub association_label: String,
+    pub country: Association<'a, Country, CallbackCountryFactory>,
+}
+
+impl<'a> Default for CallbackCityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            team_association: "teamfive".into(),
+            association_label: "thebest".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+fn trim_country_name(factory: &mut CallbackCountryFactory, _con: &PgConnection) -> QueryResult<()> {
+    factory.name = factory.name.trim().to_string();
+    Ok(())
+}
+
+fn insert_capital(
+    country: &Country,
+    factory: &CallbackCountryFactory,
+    con: &PgConnection,
+) -> QueryResult<()> {
+    if let Some(capital) = factory.capital {
+        CityFactory::default()
+            .name(capital)
+            .country(country)
+            .insert(con);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = Country, table = crate::schema::countries, id_name = identity)]
+struct CountryWithCitiesFactory {
+    pub name: String,
+    pub cities: HasMany<CityFactory<'static>>,
+    #[factory(inverse_of = country)]
+    pub residents: HasMany<UserFactory<'static>>,
+}
+
+impl Default for CountryWithCitiesFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+            cities: HasMany::count(3, CityFactory::default()),
+            residents: HasMany::default(),
+        }
+    }
+}
+
 #[derive(Clone, Factory)]
 #[factory(model = City, table = crate::schema::cities)]
 struct CityFactory<'b> {
@@ -126,84 +352,984 @@ impl<'b> Default for CityFactory<'b> {
     }
 }
 
-#[test]
-fn insert_one_user() {
-    let con = setup();
-
-    let user = UserFactory::default().name("Alice").insert(&con);
-
-    assert_eq!(user.name, "Alice");
-    assert_eq!(user.age, 30);
-    assert_eq!(1, count_users(&con));
-    assert_eq!(0, count_countries(&con));
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    id_name = identity,
+    build_with = build_country
+)]
+struct BuiltCountryFactory {
+    pub name: String,
 }
 
-#[test]
-fn overriding_country() {
-    let con = setup();
+impl Default for BuiltCountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
 
-    let bob = UserFactory::default()
-        .country(Some(CountryFactory::default().name("USA")))
-        .insert(&con);
+#[derive(Clone, Factory)]
+#[factory(model = City, table = crate::schema::cities, build_with = build_city)]
+struct LabeledCityFactory<'a> {
+    pub name: String,
+    pub team_association: String,
+    #[factory(computed = |city| format!("{} in {}", city.name, city.team_association))]
+    pub association_label: Option<String>,
+    pub country: Association<'a, Country, BuiltCountryFactory>,
+}
 
-    let country = find_country_by_id(bob.country_id.unwrap(), &con);
+impl<'a> Default for LabeledCityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            team_association: "teamfive".into(),
+            association_label: None,
+            country: Association::default(),
+        }
+    }
+}
 
-    assert_eq!("USA", country.name);
-    assert_eq!(1, count_users(&con));
-    assert_eq!(1, count_countries(&con));
+#[derive(Clone, Factory)]
+#[factory(
+    model = Membership,
+    table = crate::schema::memberships,
+    id = (i32, i32),
+    id_name = (user_id, country_id)
+)]
+struct MembershipFactory<'a> {
+    pub role: String,
+    pub user: Association<'a, User, UserFactory<'a>>,
+    pub country: Association<'a, Country, CountryFactory>,
 }
 
-#[test]
-fn insert_two_users_sharing_country() {
-    let con = setup();
+impl<'a> Default for MembershipFactory<'a> {
+    fn default() -> Self {
+        Self {
+            role: "member".into(),
+            user: Association::default(),
+            country: Association::default(),
+        }
+    }
+}
 
-    let country = CountryFactory::default().insert(&con);
-    let bob = UserFactory::default().country(Some(&country)).insert(&con);
-    let alice = UserFactory::default().country(Some(&country)).insert(&con);
+#[derive(Clone, Factory)]
+#[factory(model = MembershipNote, table = crate::schema::membership_notes)]
+struct MembershipNoteFactory<'a> {
+    pub body: String,
+    #[factory(foreign_key_name = (membership_user_id, membership_country_id))]
+    pub membership: Association<'a, Membership, MembershipFactory<'a>>,
+}
 
-    assert_eq!(bob.country_id, alice.country_id);
-    assert_eq!(2, count_users(&con));
-    assert_eq!(1, count_countries(&con));
+impl<'a> Default for MembershipNoteFactory<'a> {
+    fn default() -> Self {
+        Self {
+            body: "Joined".into(),
+            membership: Association::default(),
+        }
+    }
 }
 
-fn setup() -> PgConnection {
-    let pg_host = env::var("POSTGRES_HOST").unwrap_or_else(|_| "localhost".to_string());
-    let pg_port = env::var("POSTGRES_PORT").unwrap_or_else(|_| "5432".to_string());
-    let pg_password = env::var("POSTGRES_PASSWORD").ok();
+fn build_country(identity: i32, factory: BuiltCountryFactory) -> Country {
+    Country {
+        identity,
+        name: factory.name,
+    }
+}
 
-    let auth = if let Some(pg_password) = pg_password {
-        format!("postgres:{}@", pg_password)
-    } else {
-        String::new()
-    };
+#[derive(Clone, Factory)]
+#[factory(model = City, table = crate::schema::cities)]
+struct StandaloneCityFactory {
+    pub name: String,
+    pub team_association: String,
+    pub association_label: String,
+    pub country: OwnedAssociation<Country, CountryFactory>,
+}
 
-    let database_url = format!(
-        "postgres://{auth}{host}:{port}/diesel_factories_test",
-        auth = auth,
-        host = pg_host,
-        port = pg_port
-    );
-    let con = PgConnection::establish(&database_url).unwrap();
-    con.begin_test_transaction().unwrap();
-    con
+impl Default for StandaloneCityFactory {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            team_association: "teamfive".into(),
+            association_label: "thebest".into(),
+            country: Association::default(),
+        }
+    }
 }
 
-fn count_users(con: &PgConnection) -> i64 {
-    use crate::schema::users;
-    use diesel::dsl::count_star;
-    users::table.select(count_star()).first(con).unwrap()
+#[derive(Clone, Factory)]
+#[factory(model = Comment, table = crate::schema::comments)]
+struct CommentFactory<'a> {
+    pub body: &'a str,
+    #[factory(targets = (User: UserFactory<'a>, Country as "country": CountryFactory))]
+    pub commentable: Polymorphic<'a, PgConnection>,
 }
 
-fn count_countries(con: &PgConnection) -> i64 {
-    use crate::schema::countries;
-    use diesel::dsl::count_star;
-    countries::table.select(count_star()).first(con).unwrap()
+impl<'a> Default for CommentFactory<'a> {
+    fn default() -> Self {
+        Self {
+            body: "Nice",
+            commentable: Polymorphic::new("User", UserFactory::default()),
+        }
+    }
 }
 
-fn find_country_by_id(input: i32, con: &PgConnection) -> Country {
+#[derive(Clone, Factory)]
+#[factory(model = Category, table = crate::schema::categories)]
+struct CategoryFactory<'a> {
+    pub name: String,
+    pub parent: Option<Association<'a, Category, Box<CategoryFactory<'a>>>>,
+    #[factory(inverse_of = parent)]
+    pub children: HasMany<CategoryFactory<'a>>,
+}
+
+impl<'a> Default for CategoryFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Books".into(),
+            parent: None,
+            children: HasMany::default(),
+        }
+    }
+}
+
+trait CountryName: Clone {
+    const NAME: &'static str;
+}
+
+#[derive(Clone)]
+struct Norway;
+
+impl CountryName for Norway {
+    const NAME: &'static str = "Norway";
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = Country, table = crate::schema::countries, id_name = identity)]
+struct NamedCountryFactory<N>
+where
+    N: CountryName,
+{
+    pub name: String,
+    #[factory(transient)]
+    pub named: PhantomData<N>,
+}
+
+impl<N: CountryName> Default for NamedCountryFactory<N> {
+    fn default() -> Self {
+        Self {
+            name: N::NAME.into(),
+            named: PhantomData,
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = City, table = crate::schema::cities)]
+struct NamedCityFactory<'a, N: CountryName> {
+    pub name: String,
+    pub team_association: String,
+    pub association_label: String,
+    pub country: Association<'a, Country, NamedCountryFactory<N>>,
+}
+
+impl<'a, N: CountryName> Default for NamedCityFactory<'a, N> {
+    fn default() -> Self {
+        Self {
+            name: "Oslo".into(),
+            team_association: "teamfive".into(),
+            association_label: "thebest".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(model = User, table = crate::schema::users, connection = diesel::pg::PgConnection)]
+struct BorrowingUserFactory<'a, 'm> {
+    pub name: &'a str,
+    pub age: i32,
+    pub country: Option<Association<'m, Country, CountryFactory>>,
+    pub home_city: Option<Association<'m, City, CityFactory<'m>>>,
+    pub current_city: Option<Association<'m, City, CityFactory<'m>>>,
+}
+
+impl<'a, 'm> Default for BorrowingUserFactory<'a, 'm> {
+    fn default() -> Self {
+        Self {
+            name: "Bob",
+            age: 30,
+            country: None,
+            home_city: None,
+            current_city: None,
+        }
+    }
+}
+
+// The name is borrowed for less time than the country
+fn user_named(name: &str, country: &Country, con: &PgConnection) -> User {
+    let name = name.to_uppercase();
+    BorrowingUserFactory::default()
+        .name(name.as_str())
+        .country(Some(country))
+        .insert(con)
+}
+
+fn city_in(country: Country) -> StandaloneCityFactory {
+    StandaloneCityFactory::default().country(country)
+}
+
+fn build_city(id: i32, factory: LabeledCityFactory<'_>, country_id: i32) -> City {
+    City {
+        id,
+        name: factory.name,
+        team_association: factory.team_association,
+        association_label: factory.association_label.unwrap(),
+        country_id,
+    }
+}
+
+#[test]
+fn insert_one_user() {
+    let con = setup();
+
+    let user = UserFactory::default().name("Alice").insert(&con);
+
+    assert_eq!(user.name, "Alice");
+    assert_eq!(user.age, 30);
+    assert_eq!(1, count_users(&con));
+    assert_eq!(0, count_countries(&con));
+}
+
+#[test]
+fn owned_model_associations() {
+    let con = setup();
+
+    let sweden = CountryFactory::default().name("Sweden").insert(&con);
+    let shared_sweden = Arc::new(sweden.clone());
+    let cities = StandaloneCityFactory::insert_each(
+        vec![
+            city_in(sweden.clone()).name("Stockholm"),
+            StandaloneCityFactory::default()
+                .name("Malmö")
+                .country(Arc::clone(&shared_sweden)),
+        ],
+        &con,
+    );
+
+    assert!(cities.iter().all(|city| city.country_id == sweden.identity));
+    assert!(cities
+        .iter()
+        .all(|city| city.team_association == "teamfive"));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn shared_association_is_inserted_once() {
+    let con = setup();
+
+    let denmark = Association::shared(CountryFactory::default());
+    let bob = UserFactory::default()
+        .country(denmark.clone())
+        .home_city(Some(CityFactory::default().country(denmark.clone())))
+        .current_city(Some(CityFactory::default().country(denmark.clone())))
+        .insert(&con);
+    let alice = UserFactory::default()
+        .name("Alice")
+        .country(denmark)
+        .insert(&con);
+
+    let home_city = find_city_by_id(bob.home_city_id.unwrap(), &con);
+    let current_city = find_city_by_id(bob.current_city_id.unwrap(), &con);
+    assert_eq!(bob.country_id, Some(home_city.country_id));
+    assert_eq!(home_city.country_id, current_city.country_id);
+    assert_eq!(bob.country_id, alice.country_id);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn reset_shared_association_inserts_again() {
+    let con = setup();
+
+    let denmark = Association::shared(CountryFactory::default());
+    let bob = UserFactory::default().country(denmark.clone()).insert(&con);
+    denmark.reset_shared();
+    let alice = UserFactory::default()
+        .name("Alice")
+        .country(denmark)
+        .insert(&con);
+
+    assert_ne!(bob.country_id, alice.country_id);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn shared_association_is_shared_by_builds_and_inserts() {
+    let con = setup();
+
+    let denmark = Association::shared(BuiltCountryFactory::default());
+    let first = LabeledCityFactory::default()
+        .country(denmark.clone())
+        .build();
+    let second = LabeledCityFactory::default()
+        .country(denmark.clone())
+        .build();
+    assert_eq!(first.country_id, second.country_id);
+
+    let inserted = LabeledCityFactory::default()
+        .country(denmark.clone())
+        .insert(&con);
+    let built = LabeledCityFactory::default().country(denmark).build();
+    assert_eq!(inserted.country_id, built.country_id);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn factories_with_multiple_lifetimes() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let user = user_named("alice", &country, &con);
+
+    assert_eq!(user.name, "ALICE");
+    assert_eq!(user.country_id, Some(country.identity));
+}
+
+#[test]
+fn type_generic_factories() {
+    let con = setup();
+
+    let norway = NamedCountryFactory::<Norway>::default().insert(&con);
+    let oslo = NamedCityFactory::<Norway>::default().insert(&con);
+    let bergen = NamedCityFactory::<Norway>::default()
+        .name("Bergen")
+        .country(&norway)
+        .insert(&con);
+
+    assert_eq!(norway.name, "Norway");
+    assert_eq!(find_country_by_id(oslo.country_id, &con).name, "Norway");
+    assert_eq!(bergen.country_id, norway.identity);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn self_referential_association_inserts_parents() {
+    let con = setup();
+
+    let root = CategoryFactory::default().name("Root").insert(&con);
+    let child = CategoryFactory::default().parent(Some(&root)).insert(&con);
+    let grandchild = CategoryFactory::default()
+        .parent(Some(CategoryFactory::default().parent(Some(&child))))
+        .insert(&con);
+
+    assert_eq!("Root", root.name);
+    assert_eq!(root.parent_id, None);
+    assert_eq!(child.parent_id, Some(root.id));
+    let parent = find_category_by_id(grandchild.parent_id.unwrap(), &con);
+    assert_eq!(parent.parent_id, Some(child.id));
+}
+
+#[test]
+fn build_chain_of_categories() {
+    let con = setup();
+
+    let leaf = CategoryFactory::default()
+        .build_chain(3, |category, parent| category.parent(Some(parent)))
+        .insert(&con);
+
+    let parent = find_category_by_id(leaf.parent_id.unwrap(), &con);
+    let root = find_category_by_id(parent.parent_id.unwrap(), &con);
+    assert_eq!(root.parent_id, None);
+    assert_eq!(3, count_categories(&con));
+}
+
+#[test]
+fn build_tree_of_categories() {
+    let con = setup();
+
+    let root = CategoryFactory::default()
+        .build_tree(3, 2, |category, children| category.children(children))
+        .insert(&con);
+
+    assert_eq!(root.parent_id, None);
+    assert_eq!(7, count_categories(&con));
+}
+
+#[test]
+fn polymorphic_associations_store_type_and_id() {
+    let con = setup();
+
+    let user = UserFactory::default().insert(&con);
+    let on_user = CommentFactory::default().commentable(&user).insert(&con);
+    let on_country = CommentFactory::default()
+        .commentable(CountryFactory::default().name("Peru"))
+        .insert(&con);
+    let on_default = CommentFactory::default().insert(&con);
+
+    assert_eq!(on_user.body, "Nice");
+    assert_eq!(on_user.commentable_type, "User");
+    assert_eq!(on_user.commentable_id, user.id);
+    assert_eq!(on_country.commentable_type, "country");
+    assert_eq!(
+        find_country_by_id(on_country.commentable_id, &con).name,
+        "Peru"
+    );
+    assert_eq!(on_default.commentable_type, "User");
+    assert_eq!(2, count_users(&con));
+}
+
+#[test]
+fn overriding_country() {
+    let con = setup();
+
+    let bob = UserFactory::default()
+        .country(Some(CountryFactory::default().name("USA")))
+        .insert(&con);
+
+    let country = find_country_by_id(bob.country_id.unwrap(), &con);
+
+    assert_eq!("USA", country.name);
+    assert_eq!(1, count_users(&con));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn transient_fields_are_not_inserted() {
+    let con = setup();
+
+    let factory = CallbackCountryFactory::default()
+        .name("Sweden")
+        .locale("sv");
+    let country = factory.clone().insert(&con);
+
+    assert_eq!("sv", factory.locale);
+    assert_eq!("Sweden", country.name);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn computed_fields_use_overridden_fields() {
+    let con = setup();
+
+    let computed = LabeledCityFactory::default().name("Aarhus").insert(&con);
+    let overridden = LabeledCityFactory::default()
+        .association_label("thebest")
+        .name("Aarhus")
+        .insert(&con);
+    let built = LabeledCityFactory::default()
+        .team_association("teamsix")
+        .build();
+
+    assert_eq!("Aarhus in teamfive", computed.association_label);
+    assert_eq!("thebest", overridden.association_label);
+    assert_eq!("Copenhagen in teamsix", built.association_label);
+}
+
+#[test]
+fn computed_fields_in_insert_each() {
+    let con = setup();
+
+    let cities = LabeledCityFactory::insert_each(
+        vec![
+            LabeledCityFactory::default().name("Aarhus"),
+            LabeledCityFactory::default().association_label("thebest"),
+        ],
+        &con,
+    );
+
+    assert_eq!("Aarhus in teamfive", cities[0].association_label);
+    assert_eq!("thebest", cities[1].association_label);
+}
+
+#[test]
+fn callbacks_run_around_insert() {
+    let con = setup();
+
+    let sweden = CallbackCountryFactory::default()
+        .name("  Sweden ")
+        .capital(Some("Stockholm"))
+        .insert(&con);
+    let denmark = CallbackCountryFactory::default().insert(&con);
+
+    assert_eq!("Sweden", sweden.name);
+    assert_eq!(1, count_cities_in_country(sweden.identity, &con));
+    assert_eq!(0, count_cities_in_country(denmark.identity, &con));
+}
+
+#[test]
+fn callbacks_run_for_associations() {
+    let con = setup();
+
+    let city = CallbackCityFactory::default()
+        .name("Aarhus")
+        .country(CallbackCountryFactory::default().capital(Some("Copenhagen")))
+        .insert(&con);
+
+    assert_eq!(2, count_cities_in_country(city.country_id, &con));
+}
+
+#[test]
+fn variants_combine_with_builder_methods() {
+    let con = setup();
+
+    let sam = UserWithVariantsFactory::default()
+        .american()
+        .teenager()
+        .insert(&con);
+    let alice = UserWithVariantsFactory::default()
+        .american()
+        .name("Alice")
+        .insert(&con);
+
+    let country = find_country_by_id(sam.country_id.unwrap(), &con);
+
+    assert_eq!("Sam", sam.name);
+    assert_eq!(16, sam.age);
+    assert_eq!("USA", country.name);
+    assert_eq!("Alice", alice.name);
+    assert_eq!(30, alice.age);
+}
+
+#[test]
+fn insert_two_users_sharing_country() {
+    let con = setup();
+
+    let country = CountryFactory::default().insert(&con);
+    let bob = UserFactory::default().country(Some(&country)).insert(&con);
+    let alice = UserFactory::default().country(Some(&country)).insert(&con);
+
+    assert_eq!(bob.country_id, alice.country_id);
+    assert_eq!(2, count_users(&con));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn try_insert_returns_model() {
+    let con = setup();
+
+    let country = CountryFactory::default().try_insert(&con).unwrap();
+
+    assert_eq!("Denmark", country.name);
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn try_insert_city_without_country_fails() {
+    let con = setup();
+
+    let missing_country = Country {
+        identity: -1,
+        name: "Atlantis".into(),
+    };
+    let err = CityFactory::default()
+        .country(&missing_country)
+        .try_insert(&con)
+        .err()
+        .expect("Insert should fail");
+
+    assert_eq!("CityFactory", err.factory_name());
+    assert_eq!("cities", err.table_name());
+    assert!(err.association_path().is_empty());
+    match err.diesel_error() {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+            _,
+        ) => {}
+        other => panic!("Unexpected error {:?}", other),
+    }
+}
+
+#[test]
+fn try_insert_reports_failing_association() {
+    let con = setup();
+
+    let missing_country = Country {
+        identity: -1,
+        name: "Atlantis".into(),
+    };
+    let err = UserFactory::default()
+        .home_city(Some(CityFactory::default().country(&missing_country)))
+        .try_insert(&con)
+        .err()
+        .expect("Insert should fail");
+
+    assert_eq!("CityFactory", err.factory_name());
+    assert_eq!("cities", err.table_name());
+    assert_eq!(&["home_city"], err.association_path());
+    assert_eq!(
+        "Insert of `CityFactory` into `cities` failed (through association `home_city`): \
+         insert or update on table \"cities\" violates foreign key constraint \"cities_country_id_fkey\"",
+        err.to_string(),
+    );
+}
+
+#[test]
+fn insert_many_countries() {
+    let con = setup();
+
+    let countries = CountryFactory::default()
+        .name("Sweden")
+        .insert_many(3, &con);
+
+    assert_eq!(3, countries.len());
+    assert!(countries.iter().all(|country| country.name == "Sweden"));
+    assert_eq!(3, count_countries(&con));
+}
+
+#[test]
+fn insert_each_keeps_order_and_inserts_associations() {
+    let con = setup();
+
+    let denmark = CountryFactory::default().insert(&con);
+    let cities = CityFactory::insert_each(
+        vec![
+            CityFactory::default().name("Copenhagen").country(&denmark),
+            CityFactory::default()
+                .name("Amsterdam")
+                .country(CountryFactory::default().name("Netherlands")),
+            CityFactory::default().name("Aarhus").country(&denmark),
+        ],
+        &con,
+    );
+
+    let names = cities
+        .iter()
+        .map(|city| city.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Copenhagen", "Amsterdam", "Aarhus"], names);
+    assert_eq!(denmark.identity, cities[0].country_id);
+    assert_eq!(
+        "Netherlands",
+        find_country_by_id(cities[1].country_id, &con).name
+    );
+    assert_eq!(denmark.identity, cities[2].country_id);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn insert_each_inserts_optional_associations_in_order() {
+    let con = setup();
+
+    let users = UserFactory::insert_each(
+        vec![
+            UserFactory::default().country(Some(CountryFactory::default().name("Sweden"))),
+            UserFactory::default(),
+            UserFactory::default().country(Some(CountryFactory::default().name("Norway"))),
+        ],
+        &con,
+    );
+
+    assert_eq!(
+        "Sweden",
+        find_country_by_id(users[0].country_id.unwrap(), &con).name
+    );
+    assert_eq!(None, users[1].country_id);
+    assert_eq!(
+        "Norway",
+        find_country_by_id(users[2].country_id.unwrap(), &con).name
+    );
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn insert_each_with_no_factories() {
+    let con = setup();
+
+    let users = UserFactory::insert_each(Vec::new(), &con);
+
+    assert!(users.is_empty());
+    assert_eq!(0, count_users(&con));
+}
+
+#[test]
+fn build_list_customizes_by_index() {
+    let factories = CountryFactory::default()
+        .build_list(3, |i, country| country.name(format!("Country {}", i)));
+
+    let names = factories
+        .iter()
+        .map(|country| country.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Country 0", "Country 1", "Country 2"], names);
+}
+
+#[test]
+fn insert_list_customizes_by_index() {
+    let con = setup();
+
+    let users = UserFactory::default().insert_list(2, |i, user| user.age(20 + i as i32), &con);
+
+    assert_eq!(20, users[0].age);
+    assert_eq!(21, users[1].age);
+    assert_eq!(2, count_users(&con));
+}
+
+#[test]
+fn build_without_database() {
+    let country = Country {
+        identity: 42,
+        name: "Denmark".into(),
+    };
+
+    let city = LabeledCityFactory::default()
+        .name("Aarhus")
+        .country(&country)
+        .build();
+
+    assert_eq!("Aarhus", city.name);
+    assert_eq!(42, city.country_id);
+}
+
+#[test]
+fn build_assigns_ids_and_builds_associations() {
+    let a = LabeledCityFactory::default().build();
+    let b = LabeledCityFactory::default().build();
+
+    assert!(a.id > 0);
+    assert_ne!(a.id, b.id);
+    assert_ne!(a.country_id, b.country_id);
+}
+
+#[test]
+fn has_many_inserts_children_after_parent() {
+    let con = setup();
+
+    let country = CountryWithCitiesFactory::default().insert(&con);
+
+    assert_eq!(1, count_countries(&con));
+    assert_eq!(3, count_cities_in_country(country.identity, &con));
+}
+
+#[test]
+fn insert_with_has_many_returns_children() {
+    let con = setup();
+
+    let (country, cities) = CountryWithCitiesFactory::default()
+        .cities(vec![
+            CityFactory::default().name("Copenhagen"),
+            CityFactory::default().name("Aarhus"),
+        ])
+        .insert_with_cities(&con);
+
+    let names = cities
+        .iter()
+        .map(|city| city.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Copenhagen", "Aarhus"], names);
+    assert!(cities
+        .iter()
+        .all(|city| city.country_id == country.identity));
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn has_many_with_optional_inverse_association() {
+    let con = setup();
+
+    let (country, residents) = CountryWithCitiesFactory::default()
+        .cities(HasMany::default())
+        .residents(HasMany::count(2, UserFactory::default()))
+        .insert_with_residents(&con);
+
+    assert_eq!(2, residents.len());
+    assert!(residents
+        .iter()
+        .all(|user| user.country_id == Some(country.identity)));
+    assert_eq!(2, count_users(&con));
+}
+
+#[test]
+fn many_to_many_inserts_join_rows() {
+    let con = setup();
+
+    let user = UserWithTeamsFactory::default()
+        .teams(vec![TeamFactory::default().name("Red")])
+        .insert(&con);
+
+    assert_eq!(vec!["Red"], team_names_for_user(user.id, &con));
+    assert_eq!(1, count_teams(&con));
+}
+
+#[test]
+fn many_to_many_reports_failing_join_row() {
+    let con = setup();
+
+    let blue = TeamFactory::default().name("Blue").insert(&con);
+    let err = UserWithTeamsFactory::default()
+        .teams(vec![&blue, &blue])
+        .try_insert(&con)
+        .err()
+        .expect("Duplicate join rows should fail");
+
+    assert_eq!("UserWithTeamsFactory", err.factory_name());
+    assert_eq!("team_memberships", err.table_name());
+}
+
+#[test]
+fn many_to_many_reuses_inserted_models() {
+    let con = setup();
+
+    let red = TeamFactory::default().name("Red").insert(&con);
+    let blue = TeamFactory::default().name("Blue").insert(&con);
+    let alice = UserWithTeamsFactory::default()
+        .teams(vec![&red, &blue])
+        .insert(&con);
+    let bob = UserWithTeamsFactory::default()
+        .teams(vec![&blue])
+        .insert(&con);
+
+    assert_eq!("Blue", blue.name);
+    assert_eq!(vec!["Blue", "Red"], team_names_for_user(alice.id, &con));
+    assert_eq!(vec!["Blue"], team_names_for_user(bob.id, &con));
+    assert_eq!(2, count_teams(&con));
+}
+
+#[test]
+fn insert_with_composite_primary_key() {
+    let con = setup();
+
+    let user = UserFactory::default().insert(&con);
+    let membership = MembershipFactory::default()
+        .user(&user)
+        .role("admin")
+        .insert(&con);
+
+    assert_eq!(user.id, membership.user_id);
+    assert_eq!("admin", membership.role);
+    assert_eq!(
+        (membership.user_id, membership.country_id),
+        MembershipFactory::id_for_model(&membership)
+    );
+    assert_eq!(1, count_countries(&con));
+}
+
+#[test]
+fn composite_foreign_key_inserts_association() {
+    let con = setup();
+
+    let note = MembershipNoteFactory::default().insert(&con);
+
+    let membership = {
+        use crate::schema::memberships::dsl::*;
+        memberships
+            .find((note.membership_user_id, note.membership_country_id))
+            .first::<Membership>(&con)
+            .unwrap()
+    };
+    assert_eq!("Joined", note.body);
+    assert_eq!("member", membership.role);
+}
+
+#[test]
+fn composite_foreign_key_with_existing_model() {
+    let con = setup();
+
+    let membership = MembershipFactory::default().insert(&con);
+    let notes = MembershipNoteFactory::insert_each(
+        vec![
+            MembershipNoteFactory::default().membership(&membership),
+            MembershipNoteFactory::default().membership(&membership),
+        ],
+        &con,
+    );
+
+    assert!(notes.iter().all(|note| {
+        (note.membership_user_id, note.membership_country_id)
+            == (membership.user_id, membership.country_id)
+    }));
+    assert_eq!(1, count_users(&con));
+}
+
+fn setup() -> PgConnection {
+    let pg_host = env::var("POSTGRES_HOST").unwrap_or_else(|_| "localhost".to_string());
+    let pg_port = env::var("POSTGRES_PORT").unwrap_or_else(|_| "5432".to_string());
+    let pg_password = env::var("POSTGRES_PASSWORD").ok();
+
+    let auth = if let Some(pg_password) = pg_password {
+        format!("postgres:{}@", pg_password)
+    } else {
+        String::new()
+    };
+
+    let database_url = format!(
+        "postgres://{auth}{host}:{port}/diesel_factories_test",
+        auth = auth,
+        host = pg_host,
+        port = pg_port
+    );
+    let con = PgConnection::establish(&database_url).unwrap();
+    con.begin_test_transaction().unwrap();
+    con
+}
+
+fn count_users(con: &PgConnection) -> i64 {
+    use crate::schema::users;
+    use diesel::dsl::count_star;
+    users::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_countries(con: &PgConnection) -> i64 {
+    use crate::schema::countries;
+    use diesel::dsl::count_star;
+    countries::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_teams(con: &PgConnection) -> i64 {
+    use crate::schema::teams;
+    use diesel::dsl::count_star;
+    teams::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_categories(con: &PgConnection) -> i64 {
+    use crate::schema::categories;
+    use diesel::dsl::count_star;
+    categories::table.select(count_star()).first(con).unwrap()
+}
+
+fn find_category_by_id(input: i32, con: &PgConnection) -> Category {
+    use crate::schema::categories::dsl::*;
+    categories
+        .filter(id.eq(input))
+        .first::<Category>(con)
+        .unwrap()
+}
+
+fn team_names_for_user(user_id: i32, con: &PgConnection) -> Vec<String> {
+    use crate::schema::{team_memberships, teams};
+    let team_ids = team_memberships::table
+        .filter(team_memberships::user_id.eq(user_id))
+        .select(team_memberships::team_id)
+        .load::<i32>(con)
+        .unwrap();
+    teams::table
+        .filter(teams::id.eq_any(team_ids))
+        .select(teams::name)
+        .order(teams::name)
+        .load(con)
+        .unwrap()
+}
+
+fn count_cities_in_country(input: i32, con: &PgConnection) -> i64 {
+    use crate::schema::cities::dsl::*;
+    use diesel::dsl::count_star;
+    cities
+        .filter(country_id.eq(input))
+        .select(count_star())
+        .first(con)
+        .unwrap()
+}
+
+fn find_country_by_id(input: i32, con: &PgConnection) -> Country {
     use crate::schema::countries::dsl::*;
     countries
         .filter(identity.eq(&input))
         .first::<Country>(con)
         .unwrap()
 }
+
+fn find_city_by_id(input: i32, con: &PgConnection) -> City {
+    use crate::schema::cities::dsl::*;
+    cities.filter(id.eq(&input)).first::<City>(con).unwrap()
+}
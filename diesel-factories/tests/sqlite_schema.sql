@@ -0,0 +1,30 @@
+-- Schema for `sqlite_test.rs`, mirroring the tables of the Postgres migrations it needs
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE countries (
+  identity INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL
+);
+
+CREATE TABLE cities (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  country_id INTEGER NOT NULL REFERENCES countries (identity)
+);
+
+CREATE TABLE capitals (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  country_name TEXT NOT NULL
+);
+
+CREATE TABLE currencies (
+  code TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL
+);
+
+CREATE TABLE country_currencies (
+  country_id INTEGER NOT NULL REFERENCES countries (identity),
+  currency_code TEXT NOT NULL REFERENCES currencies (code),
+  PRIMARY KEY (country_id, currency_code)
+);
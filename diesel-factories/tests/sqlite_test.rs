@@ -0,0 +1,311 @@
+#![allow(proc_macro_derive_resolution_fallback)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::{connection::SimpleConnection, prelude::*, sqlite::SqliteConnection};
+use diesel_factories::{Association, Factory, ManyToMany};
+
+mod schema {
+    table! {
+        countries (identity) {
+            identity -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        capitals (id) {
+            id -> Integer,
+            name -> Text,
+            country_name -> Text,
+        }
+    }
+
+    table! {
+        currencies (code) {
+            code -> Text,
+            name -> Text,
+        }
+    }
+
+    table! {
+        country_currencies (country_id, currency_code) {
+            country_id -> Integer,
+            currency_code -> Text,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub identity: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Capital {
+    pub id: i32,
+    pub name: String,
+    pub country_name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Currency {
+    pub code: String,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::sqlite::SqliteConnection,
+    id_name = identity
+)]
+struct CountryFactory<'a> {
+    pub name: String,
+    #[factory(through = crate::schema::country_currencies, target_key = currency_code)]
+    pub currencies: ManyToMany<'a, Currency, CurrencyFactory>,
+}
+
+impl<'a> Default for CountryFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+            currencies: ManyToMany::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = City,
+    table = crate::schema::cities,
+    connection = diesel::sqlite::SqliteConnection
+)]
+struct CityFactory<'a> {
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory<'a>>,
+}
+
+impl<'a> Default for CityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Capital,
+    table = crate::schema::capitals,
+    connection = diesel::sqlite::SqliteConnection
+)]
+struct CapitalFactory<'a> {
+    pub name: String,
+    #[factory(references = name)]
+    pub country: Association<'a, Country, CountryFactory<'a>>,
+}
+
+impl<'a> Default for CapitalFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Currency,
+    table = crate::schema::currencies,
+    connection = diesel::sqlite::SqliteConnection,
+    id = String,
+    id_name = code
+)]
+struct CurrencyFactory {
+    pub code: String,
+    pub name: String,
+}
+
+impl Default for CurrencyFactory {
+    fn default() -> Self {
+        Self {
+            code: "DKK".into(),
+            name: "Danish krone".into(),
+        }
+    }
+}
+
+#[test]
+fn insert_returns_inserted_row() {
+    let con = setup();
+
+    let first = CountryFactory::default().insert(&con);
+    let second = CountryFactory::default().name("Sweden").insert(&con);
+
+    assert_eq!("Denmark", first.name);
+    assert_eq!("Sweden", second.name);
+    assert_ne!(first.identity, second.identity);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn insert_with_association() {
+    let con = setup();
+
+    let city = CityFactory::default()
+        .country(CountryFactory::default().name("Netherlands"))
+        .insert(&con);
+
+    assert_eq!("Copenhagen", city.name);
+    assert_eq!(
+        "Netherlands",
+        find_country_by_id(city.country_id, &con).name
+    );
+}
+
+#[test]
+fn association_referencing_other_column() {
+    let con = setup();
+
+    let sweden = CountryFactory::default().name("Sweden").insert(&con);
+    let stockholm = CapitalFactory::default()
+        .name("Stockholm")
+        .country(&sweden)
+        .insert(&con);
+    let copenhagen = CapitalFactory::default().insert(&con);
+
+    assert_eq!("Stockholm", stockholm.name);
+    assert_eq!("Sweden", stockholm.country_name);
+    assert_eq!("Denmark", copenhagen.country_name);
+    assert_eq!(2, count_countries(&con));
+}
+
+#[test]
+fn insert_each_inserts_one_row_at_a_time() {
+    let con = setup();
+
+    let denmark = CountryFactory::default().insert(&con);
+    let cities = CityFactory::insert_each(
+        vec![
+            CityFactory::default().name("Copenhagen").country(&denmark),
+            CityFactory::default().name("Aarhus").country(&denmark),
+        ],
+        &con,
+    );
+
+    let names = cities
+        .iter()
+        .map(|city| city.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Copenhagen", "Aarhus"], names);
+    assert_ne!(cities[0].id, cities[1].id);
+    assert!(cities
+        .iter()
+        .all(|city| city.country_id == denmark.identity));
+}
+
+#[test]
+fn insert_with_client_supplied_primary_key() {
+    let con = setup();
+
+    let krone = CurrencyFactory::default().insert(&con);
+    let euro = CurrencyFactory::default()
+        .code("EUR")
+        .name("Euro")
+        .insert(&con);
+
+    assert_eq!("DKK", krone.code);
+    assert_eq!("Danish krone", krone.name);
+    assert_eq!("EUR", euro.code);
+    assert_eq!("Euro", euro.name);
+}
+
+#[test]
+fn many_to_many_inserts_join_rows() {
+    let con = setup();
+
+    let euro = CurrencyFactory::default()
+        .code("EUR")
+        .name("Euro")
+        .insert(&con);
+    let country = CountryFactory::default()
+        .currencies(vec![CurrencyFactory::default()])
+        .insert(&con);
+    CountryFactory::default()
+        .name("Germany")
+        .currencies(vec![&euro])
+        .insert(&con);
+
+    let codes = {
+        use crate::schema::country_currencies::dsl::*;
+        country_currencies
+            .filter(country_id.eq(country.identity))
+            .select(currency_code)
+            .load::<String>(&con)
+            .unwrap()
+    };
+    assert_eq!(vec!["DKK"], codes);
+}
+
+#[test]
+fn try_insert_city_without_country_fails() {
+    let con = setup();
+
+    let missing_country = Country {
+        identity: -1,
+        name: "Atlantis".into(),
+    };
+    let err = CityFactory::default()
+        .country(&missing_country)
+        .try_insert(&con)
+        .err()
+        .expect("Insert should fail");
+
+    assert_eq!("CityFactory", err.factory_name());
+    assert_eq!("cities", err.table_name());
+}
+
+fn setup() -> SqliteConnection {
+    let con = SqliteConnection::establish(":memory:").unwrap();
+    // The migrations are Postgres only. They use `SERIAL` columns, PL/pgSQL functions and
+    // `ALTER TABLE ... ADD CONSTRAINT`, none of which SQLite supports, so the tables used here
+    // are created from a fixture instead.
+    con.batch_execute(include_str!("sqlite_schema.sql"))
+        .unwrap();
+    con
+}
+
+fn count_countries(con: &SqliteConnection) -> i64 {
+    use crate::schema::countries;
+    use diesel::dsl::count_star;
+    countries::table.select(count_star()).first(con).unwrap()
+}
+
+fn find_country_by_id(input: i32, con: &SqliteConnection) -> Country {
+    use crate::schema::countries::dsl::*;
+    countries
+        .filter(identity.eq(&input))
+        .first::<Country>(con)
+        .unwrap()
+}
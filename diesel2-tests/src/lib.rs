@@ -0,0 +1 @@
+//! Tests for using diesel-factories with Diesel 2. See the `tests` directory.
@@ -0,0 +1,380 @@
+#[macro_use]
+extern crate diesel;
+
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use diesel_factories::{Association, AsyncFactory, Factory, HasMany, ManyToMany};
+use std::env;
+
+mod schema {
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+            age -> Integer,
+            country_id -> Nullable<Integer>,
+            home_city_id -> Nullable<Integer>,
+            current_city_id -> Nullable<Integer>,
+        }
+    }
+
+    table! {
+        countries (identity) {
+            identity -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        teams (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        team_memberships (user_id, team_id) {
+            user_id -> Integer,
+            team_id -> Integer,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+            association_label -> Nullable<Text>,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+    pub country_id: Option<i32>,
+    pub home_city_id: Option<i32>,
+    pub current_city_id: Option<i32>,
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub identity: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct Team {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+    pub association_label: Option<String>,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = User,
+    table = crate::schema::users,
+    async_connection = diesel_async::AsyncPgConnection
+)]
+struct UserFactory<'a> {
+    pub name: &'a str,
+    pub age: i32,
+    pub country: Option<Association<'a, Country, CountryFactory>>,
+    pub home_city: Option<Association<'a, City, CityFactory<'a>>>,
+    #[factory(through = crate::schema::team_memberships)]
+    pub teams: ManyToMany<'a, Team, TeamFactory>,
+}
+
+impl<'a> Default for UserFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Bob",
+            age: 30,
+            country: None,
+            home_city: None,
+            teams: ManyToMany::default(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Team,
+    table = crate::schema::teams,
+    async_connection = diesel_async::AsyncPgConnection
+)]
+struct TeamFactory {
+    pub name: String,
+}
+
+impl Default for TeamFactory {
+    fn default() -> Self {
+        Self { name: "Red".into() }
+    }
+}
+
+// Implements both `Factory` and `AsyncFactory`
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::pg::PgConnection,
+    async_connection = diesel_async::AsyncPgConnection,
+    id_name = identity
+)]
+struct CountryFactory {
+    pub name: String,
+}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    async_connection = diesel_async::AsyncPgConnection,
+    id_name = identity
+)]
+struct CountryWithCitiesFactory {
+    pub name: String,
+    pub cities: HasMany<CityFactory<'static>>,
+}
+
+impl Default for CountryWithCitiesFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+            cities: HasMany::count(2, CityFactory::default()),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = City,
+    table = crate::schema::cities,
+    async_connection = diesel_async::AsyncPgConnection
+)]
+struct CityFactory<'a> {
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+    #[factory(computed = |city| format!("{} label", city.name))]
+    pub association_label: Option<String>,
+}
+
+impl<'a> Default for CityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+            association_label: None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn insert_one_user() {
+    let mut con = setup().await;
+
+    let user = UserFactory::default().name("Alice").insert(&mut con).await;
+
+    assert_eq!(user.name, "Alice");
+    assert_eq!(1, count_users(&mut con).await);
+    assert_eq!(0, count_countries(&mut con).await);
+}
+
+#[tokio::test]
+async fn insert_nested_associations() {
+    let mut con = setup().await;
+
+    let user = UserFactory::default()
+        .country(Some(CountryFactory::default().name("USA")))
+        .home_city(Some(CityFactory::default().name("Aarhus")))
+        .insert(&mut con)
+        .await;
+
+    let country = find_country_by_id(user.country_id.unwrap(), &mut con).await;
+    assert_eq!("USA", country.name);
+    assert!(user.home_city_id.is_some());
+    assert_eq!(2, count_countries(&mut con).await);
+}
+
+#[tokio::test]
+async fn insert_shared_association() {
+    let mut con = setup().await;
+
+    let usa = Association::shared(CountryFactory::default().name("USA"));
+    let user = UserFactory::default()
+        .country(usa.clone())
+        .home_city(Some(CityFactory::default().country(usa)))
+        .insert(&mut con)
+        .await;
+
+    assert!(user.country_id.is_some());
+    assert_eq!(1, count_countries(&mut con).await);
+}
+
+#[tokio::test]
+async fn insert_computed_fields() {
+    let mut con = setup().await;
+
+    let computed = CityFactory::default().name("Aarhus").insert(&mut con).await;
+    let overridden = CityFactory::default()
+        .association_label("Custom")
+        .insert(&mut con)
+        .await;
+
+    assert_eq!(Some("Aarhus label".into()), computed.association_label);
+    assert_eq!(Some("Custom".into()), overridden.association_label);
+}
+
+#[tokio::test]
+async fn insert_sharing_association() {
+    let mut con = setup().await;
+
+    let country = AsyncFactory::insert(CountryFactory::default(), &mut con).await;
+    let a = CityFactory::default()
+        .country(&country)
+        .insert(&mut con)
+        .await;
+    let b = CityFactory::default()
+        .country(&country)
+        .insert(&mut con)
+        .await;
+
+    assert_eq!(country.identity, a.country_id);
+    assert_eq!(country.identity, b.country_id);
+    assert_eq!(1, count_countries(&mut con).await);
+}
+
+#[tokio::test]
+async fn try_insert_reports_failing_association() {
+    let mut con = setup().await;
+
+    let missing_country = Country {
+        identity: -1,
+        name: "Atlantis".into(),
+    };
+    let err = UserFactory::default()
+        .home_city(Some(CityFactory::default().country(&missing_country)))
+        .try_insert(&mut con)
+        .await
+        .err()
+        .expect("Insert should fail");
+
+    assert_eq!("CityFactory", err.factory_name());
+    assert_eq!(&["home_city"], err.association_path());
+}
+
+#[tokio::test]
+async fn insert_has_many() {
+    let mut con = setup().await;
+
+    CountryWithCitiesFactory::default().insert(&mut con).await;
+
+    assert_eq!(1, count_countries(&mut con).await);
+    assert_eq!(2, count_cities(&mut con).await);
+}
+
+#[tokio::test]
+async fn insert_many_to_many() {
+    let mut con = setup().await;
+
+    let blue = TeamFactory::default().name("Blue").insert(&mut con).await;
+    let user = UserFactory::default()
+        .teams(vec![&blue])
+        .insert(&mut con)
+        .await;
+
+    let team_ids = {
+        use crate::schema::team_memberships::dsl::*;
+        team_memberships
+            .filter(user_id.eq(user.id))
+            .select(team_id)
+            .load::<i32>(&mut con)
+            .await
+            .unwrap()
+    };
+    assert_eq!(vec![blue.id], team_ids);
+}
+
+#[test]
+fn sync_and_async_factory() {
+    let mut con = PgConnection::establish(&database_url()).unwrap();
+    con.begin_test_transaction().unwrap();
+
+    let country = Factory::insert(CountryFactory::default(), &mut con);
+
+    assert_eq!("Denmark", country.name);
+}
+
+async fn setup() -> AsyncPgConnection {
+    let mut con = AsyncPgConnection::establish(&database_url()).await.unwrap();
+    con.begin_test_transaction().await.unwrap();
+    con
+}
+
+fn database_url() -> String {
+    let pg_host = env::var("POSTGRES_HOST").unwrap_or_else(|_| "localhost".to_string());
+    let pg_port = env::var("POSTGRES_PORT").unwrap_or_else(|_| "5432".to_string());
+    let pg_password = env::var("POSTGRES_PASSWORD").ok();
+
+    let auth = if let Some(pg_password) = pg_password {
+        format!("postgres:{}@", pg_password)
+    } else {
+        String::new()
+    };
+
+    format!(
+        "postgres://{auth}{host}:{port}/diesel_factories_test",
+        auth = auth,
+        host = pg_host,
+        port = pg_port
+    )
+}
+
+async fn count_users(con: &mut AsyncPgConnection) -> i64 {
+    use crate::schema::users;
+    use diesel::dsl::count_star;
+    users::table.select(count_star()).first(con).await.unwrap()
+}
+
+async fn count_cities(con: &mut AsyncPgConnection) -> i64 {
+    use crate::schema::cities;
+    use diesel::dsl::count_star;
+    cities::table.select(count_star()).first(con).await.unwrap()
+}
+
+async fn count_countries(con: &mut AsyncPgConnection) -> i64 {
+    use crate::schema::countries;
+    use diesel::dsl::count_star;
+    countries::table
+        .select(count_star())
+        .first(con)
+        .await
+        .unwrap()
+}
+
+async fn find_country_by_id(input: i32, con: &mut AsyncPgConnection) -> Country {
+    use crate::schema::countries::dsl::*;
+    countries
+        .filter(identity.eq(&input))
+        .first::<Country>(con)
+        .await
+        .unwrap()
+}
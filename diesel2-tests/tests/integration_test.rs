@@ -0,0 +1,326 @@
+#[macro_use]
+extern crate diesel;
+
+use diesel::{pg::PgConnection, prelude::*};
+use diesel_factories::{fake, seed_rng, Association, Factory, HasMany};
+use std::env;
+
+mod schema {
+    table! {
+        users (id) {
+            id -> Integer,
+            name -> Text,
+            age -> Integer,
+            country_id -> Nullable<Integer>,
+            home_city_id -> Nullable<Integer>,
+            current_city_id -> Nullable<Integer>,
+        }
+    }
+
+    table! {
+        countries (identity) {
+            identity -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+struct User {
+    pub id: i32,
+    pub name: String,
+    pub age: i32,
+    pub country_id: Option<i32>,
+    pub home_city_id: Option<i32>,
+    pub current_city_id: Option<i32>,
+}
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub identity: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = User,
+    table = crate::schema::users,
+    connection = diesel::pg::PgConnection
+)]
+struct UserFactory<'a> {
+    pub name: &'a str,
+    pub age: i32,
+    pub country: Option<Association<'a, Country, CountryFactory>>,
+}
+
+impl<'a> Default for UserFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Bob",
+            age: 30,
+            country: None,
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::pg::PgConnection,
+    id_name = identity
+)]
+struct CountryFactory {
+    pub name: String,
+}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::pg::PgConnection,
+    id_name = identity,
+    after_insert = insert_resident
+)]
+struct CountryWithCitiesFactory {
+    pub name: String,
+    pub cities: HasMany<CityFactory<'static>>,
+    #[factory(transient)]
+    pub resident: Option<&'static str>,
+}
+
+impl Default for CountryWithCitiesFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+            cities: HasMany::count(2, CityFactory::default()),
+            resident: None,
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::pg::PgConnection,
+    id_name = identity
+)]
+struct FakeCountryFactory {
+    #[factory(fake = "country")]
+    pub name: String,
+}
+
+fn insert_resident(
+    country: &Country,
+    factory: &CountryWithCitiesFactory,
+    con: &mut PgConnection,
+) -> QueryResult<()> {
+    if let Some(resident) = factory.resident {
+        UserFactory::default()
+            .name(resident)
+            .country(country)
+            .insert(con);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = City,
+    table = crate::schema::cities,
+    connection = diesel::pg::PgConnection
+)]
+struct CityFactory<'a> {
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+impl<'a> Default for CityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+#[test]
+fn insert_one_user() {
+    let mut con = setup();
+
+    let user = UserFactory::default().name("Alice").insert(&mut con);
+
+    assert_eq!(user.name, "Alice");
+    assert_eq!(user.age, 30);
+    assert_eq!(1, count_users(&mut con));
+    assert_eq!(0, count_countries(&mut con));
+}
+
+#[test]
+fn insert_with_associations() {
+    let mut con = setup();
+
+    let bob = UserFactory::default()
+        .country(Some(CountryFactory::default().name("USA")))
+        .insert(&mut con);
+    let city = CityFactory::default().insert(&mut con);
+
+    let country = find_country_by_id(bob.country_id.unwrap(), &mut con);
+    assert_eq!("USA", country.name);
+    let country = find_country_by_id(city.country_id, &mut con);
+    assert_eq!("Denmark", country.name);
+    assert_eq!(2, count_countries(&mut con));
+}
+
+#[test]
+fn insert_sharing_association() {
+    let mut con = setup();
+
+    let country = CountryFactory::default().insert(&mut con);
+    let cities = CityFactory::insert_each(
+        vec![
+            CityFactory::default().country(&country),
+            CityFactory::default().country(&country),
+        ],
+        &mut con,
+    );
+
+    assert_eq!(country.identity, cities[0].country_id);
+    assert_eq!(country.identity, cities[1].country_id);
+    assert_eq!(1, count_countries(&mut con));
+}
+
+#[test]
+fn try_insert_city_without_country_fails() {
+    let mut con = setup();
+
+    let missing_country = Country {
+        identity: -1,
+        name: "Atlantis".into(),
+    };
+    let err = CityFactory::default()
+        .country(&missing_country)
+        .try_insert(&mut con)
+        .err()
+        .expect("Insert should fail");
+
+    assert_eq!("CityFactory", err.factory_name());
+}
+
+#[test]
+fn insert_with_has_many() {
+    let mut con = setup();
+
+    let (country, cities) = CountryWithCitiesFactory::default().insert_with_cities(&mut con);
+
+    assert_eq!(2, cities.len());
+    assert!(cities
+        .iter()
+        .all(|city| city.country_id == country.identity));
+    assert_eq!(1, count_countries(&mut con));
+}
+
+#[test]
+fn after_insert_callback() {
+    let mut con = setup();
+
+    let country = CountryWithCitiesFactory::default()
+        .resident(Some("Alice"))
+        .insert(&mut con);
+
+    assert_eq!("Denmark", country.name);
+    assert_eq!(1, count_users(&mut con));
+}
+
+#[test]
+fn fake_defaults() {
+    let mut con = setup();
+
+    seed_rng(1);
+    let first = FakeCountryFactory::default().insert(&mut con);
+    seed_rng(1);
+    let second = FakeCountryFactory::default().insert(&mut con);
+    let named = FakeCountryFactory::default()
+        .name("Atlantis")
+        .insert(&mut con);
+
+    assert!(!first.name.is_empty());
+    assert_eq!(first.name, second.name);
+    assert_eq!("Atlantis", named.name);
+}
+
+#[test]
+fn fake_generators() {
+    let email = fake::unique_email();
+
+    assert!(email.ends_with("@example.com"));
+    assert_ne!(email, fake::unique_email());
+    assert!(fake::sentence().ends_with('.'));
+    assert!(fake::name().contains(' '));
+}
+
+fn setup() -> PgConnection {
+    let pg_host = env::var("POSTGRES_HOST").unwrap_or_else(|_| "localhost".to_string());
+    let pg_port = env::var("POSTGRES_PORT").unwrap_or_else(|_| "5432".to_string());
+    let pg_password = env::var("POSTGRES_PASSWORD").ok();
+
+    let auth = if let Some(pg_password) = pg_password {
+        format!("postgres:{}@", pg_password)
+    } else {
+        String::new()
+    };
+
+    let database_url = format!(
+        "postgres://{auth}{host}:{port}/diesel_factories_test",
+        auth = auth,
+        host = pg_host,
+        port = pg_port
+    );
+    let mut con = PgConnection::establish(&database_url).unwrap();
+    con.begin_test_transaction().unwrap();
+    con
+}
+
+fn count_users(con: &mut PgConnection) -> i64 {
+    use crate::schema::users;
+    use diesel::dsl::count_star;
+    users::table.select(count_star()).first(con).unwrap()
+}
+
+fn count_countries(con: &mut PgConnection) -> i64 {
+    use crate::schema::countries;
+    use diesel::dsl::count_star;
+    countries::table.select(count_star()).first(con).unwrap()
+}
+
+fn find_country_by_id(input: i32, con: &mut PgConnection) -> Country {
+    use crate::schema::countries::dsl::*;
+    countries
+        .filter(identity.eq(&input))
+        .first::<Country>(con)
+        .unwrap()
+}
@@ -0,0 +1 @@
+ALTER TABLE cities DROP CONSTRAINT cities_country_id_fkey;
@@ -0,0 +1,3 @@
+ALTER TABLE cities
+    ADD CONSTRAINT cities_country_id_fkey
+    FOREIGN KEY (country_id) REFERENCES countries (identity);
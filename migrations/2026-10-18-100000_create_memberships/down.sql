@@ -0,0 +1,2 @@
+DROP TABLE membership_notes;
+DROP TABLE memberships;
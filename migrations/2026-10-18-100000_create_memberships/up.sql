@@ -0,0 +1,15 @@
+CREATE TABLE memberships (
+  user_id integer NOT NULL REFERENCES users (id),
+  country_id integer NOT NULL REFERENCES countries (identity),
+  role TEXT NOT NULL,
+  PRIMARY KEY (user_id, country_id)
+);
+
+CREATE TABLE membership_notes (
+  id SERIAL PRIMARY KEY,
+  body TEXT NOT NULL,
+  membership_user_id integer NOT NULL,
+  membership_country_id integer NOT NULL,
+  FOREIGN KEY (membership_user_id, membership_country_id)
+    REFERENCES memberships (user_id, country_id)
+);
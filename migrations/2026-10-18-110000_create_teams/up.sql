@@ -0,0 +1,10 @@
+CREATE TABLE teams (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL
+);
+
+CREATE TABLE team_memberships (
+  user_id integer NOT NULL REFERENCES users (id),
+  team_id integer NOT NULL REFERENCES teams (id),
+  PRIMARY KEY (user_id, team_id)
+);
@@ -0,0 +1,5 @@
+CREATE TABLE categories (
+  id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  parent_id integer REFERENCES categories (id)
+);
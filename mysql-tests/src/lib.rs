@@ -0,0 +1 @@
+//! Tests for using diesel-factories with MySQL. See the `tests` directory.
@@ -0,0 +1,155 @@
+//! Compile checks for factories inserting into MySQL. Linking needs libmysqlclient and there's no
+//! MySQL database to run against, so these are only type checked with
+//! `cargo check --tests --manifest-path mysql-tests/Cargo.toml`.
+
+#![allow(proc_macro_derive_resolution_fallback, dead_code)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::mysql::MysqlConnection;
+use diesel_factories::{Association, Factory};
+
+mod schema {
+    table! {
+        countries (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    table! {
+        cities (id) {
+            id -> Integer,
+            name -> Text,
+            country_id -> Integer,
+        }
+    }
+
+    table! {
+        tags (id) {
+            id -> Integer,
+        }
+    }
+
+    table! {
+        currencies (code) {
+            code -> Text,
+            name -> Text,
+        }
+    }
+}
+
+/// The backend can't be guessed from an alias, so the factories using it set `backend = mysql`.
+type Db = MysqlConnection;
+
+#[derive(Queryable, Clone)]
+struct Country {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable, Clone)]
+struct City {
+    pub id: i32,
+    pub name: String,
+    pub country_id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Tag {
+    pub id: i32,
+}
+
+#[derive(Queryable, Clone)]
+struct Currency {
+    pub code: String,
+    pub name: String,
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Country,
+    table = crate::schema::countries,
+    connection = diesel::mysql::MysqlConnection
+)]
+struct CountryFactory {
+    pub name: String,
+}
+
+impl Default for CountryFactory {
+    fn default() -> Self {
+        Self {
+            name: "Denmark".into(),
+        }
+    }
+}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = City,
+    table = crate::schema::cities,
+    connection = Db,
+    backend = mysql
+)]
+struct CityFactory<'a> {
+    pub name: String,
+    pub country: Association<'a, Country, CountryFactory>,
+}
+
+impl<'a> Default for CityFactory<'a> {
+    fn default() -> Self {
+        Self {
+            name: "Copenhagen".into(),
+            country: Association::default(),
+        }
+    }
+}
+
+// Inserted with `default_values()`, which Diesel writes as `() VALUES ()` for MySQL
+#[derive(Clone, Default, Factory)]
+#[factory(model = Tag, table = crate::schema::tags, connection = Db, backend = mysql)]
+struct TagFactory {}
+
+#[derive(Clone, Factory)]
+#[factory(
+    model = Currency,
+    table = crate::schema::currencies,
+    connection = Db,
+    backend = mysql,
+    id = String,
+    id_name = code
+)]
+struct CurrencyFactory {
+    pub code: String,
+    pub name: String,
+}
+
+impl Default for CurrencyFactory {
+    fn default() -> Self {
+        Self {
+            code: "DKK".into(),
+            name: "Danish krone".into(),
+        }
+    }
+}
+
+fn insert_with_association(con: &Db) -> City {
+    let country = CountryFactory::default().insert(con);
+    CityFactory::default().country(&country).insert(con)
+}
+
+fn insert_many(con: &Db) -> Vec<City> {
+    CityFactory::default().insert_many(3, con)
+}
+
+fn insert_with_supplied_primary_key(con: &Db) -> Currency {
+    CurrencyFactory::default().insert(con)
+}
+
+fn insert_empty_factory(con: &Db) -> Vec<Tag> {
+    let tag = TagFactory::default().insert(con);
+    let mut tags = TagFactory::default().insert_many(2, con);
+    tags.insert(0, tag);
+    tags
+}